@@ -0,0 +1,414 @@
+use crate::cache::{CacheItemPolicy, CacheOptions, CacheSize, MemoryCacheBase};
+use crate::helpers::{SystemTimeProvider, UnixTimeProvider};
+use crate::models::{
+    DnsPacket, Name, QueryClass, QueryType, Question, RawRecord, RecordData, ResultCode,
+    MAX_CNAME_CHAIN_LENGTH,
+};
+use rustc_hash::FxHashMap;
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Share of the memory budget for negative answers, the rest goes to RRsets.
+const NEGATIVE_BUDGET_PERCENT: usize = 25;
+
+/// Identifies one RRset: all records sharing owner name, type and class.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct RRsetKey {
+    pub(in crate::cache) name: Name,
+    pub(in crate::cache) query_type: QueryType,
+    pub(in crate::cache) query_class: QueryClass,
+}
+
+impl RRsetKey {
+    pub fn new(name: &Name, query_type: QueryType, query_class: QueryClass) -> Self {
+        Self {
+            name: name.clone(),
+            query_type,
+            query_class,
+        }
+    }
+}
+
+impl CacheSize for RRsetKey {
+    fn cache_size(&self) -> usize {
+        std::mem::size_of::<Self>() + self.name.wire_len()
+    }
+}
+
+/// What a negative answer denies (RFC 2308): NXDOMAIN covers every type of
+/// the name, NODATA only the one asked for.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub(in crate::cache) enum NegativeKey {
+    NameError(Name, QueryClass),
+    NoData(RRsetKey),
+}
+
+impl CacheSize for NegativeKey {
+    fn cache_size(&self) -> usize {
+        let name = match self {
+            NegativeKey::NameError(name, _) => name,
+            NegativeKey::NoData(key) => &key.name,
+        };
+        std::mem::size_of::<Self>() + name.wire_len()
+    }
+}
+
+impl CacheSize for RawRecord {
+    fn cache_size(&self) -> usize {
+        let data = match self.data() {
+            RecordData::A(_) | RecordData::AAAA(_) => 0,
+            RecordData::NS(name) | RecordData::CNAME(name) | RecordData::PTR(name) => {
+                name.wire_len()
+            }
+            RecordData::MX { exchange, .. } => exchange.wire_len(),
+            RecordData::SOA { mname, rname, .. } => mname.wire_len() + rname.wire_len(),
+            RecordData::TXT(strings) => strings
+                .iter()
+                .map(|string| std::mem::size_of::<Vec<u8>>() + string.len())
+                .sum(),
+            RecordData::SRV { target, .. } => target.wire_len(),
+            RecordData::CAA { tag, value, .. } => tag.len() + value.len(),
+            RecordData::Unknown(data) => data.len(),
+        };
+        std::mem::size_of::<Self>() + self.name().wire_len() + data
+    }
+}
+
+/// An answer put together from the cache.
+pub struct CachedAnswer {
+    pub result_code: ResultCode,
+    pub answers: Vec<RawRecord>,
+    /// The SOA record of a negative answer.
+    pub authorities: Vec<RawRecord>,
+    /// Whether a popular entry of the answer is about to expire and the
+    /// question should be resolved again in the background.
+    pub prefetch: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub stale_hits: u64,
+    pub prefetches: u64,
+    pub entries: usize,
+    pub size_bytes: usize,
+}
+
+impl Display for CacheStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} entries ({} bytes), {} hits, {} misses, {} stale hits, {} prefetches",
+            self.entries, self.size_bytes, self.hits, self.misses, self.stale_hits, self.prefetches
+        )
+    }
+}
+
+#[derive(Default)]
+struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    stale_hits: AtomicU64,
+    prefetches: AtomicU64,
+}
+
+pub type DnsCache = DnsCacheBase<SystemTimeProvider>;
+
+impl DnsCache {
+    pub fn new(options: CacheOptions) -> Self {
+        Self::with_clock(SystemTimeProvider, options)
+    }
+}
+
+/// Cache of upstream answers stored per RRset, so that e.g. a cached CNAME
+/// is reused whatever type is asked for its owner. Negative answers are kept
+/// apart along with the SOA record they came with. Records are served with
+/// their TTLs counting down from the time they were cached.
+pub struct DnsCacheBase<T: UnixTimeProvider> {
+    pub(in crate::cache) rrsets: MemoryCacheBase<RRsetKey, Vec<RawRecord>, T>,
+    pub(in crate::cache) negatives: MemoryCacheBase<NegativeKey, RawRecord, T>,
+    stats: Counters,
+}
+
+impl<T: UnixTimeProvider> DnsCacheBase<T> {
+    pub fn with_clock(clock: T, options: CacheOptions) -> Self {
+        Self {
+            rrsets: MemoryCacheBase::with_clock(
+                clock.clone(),
+                options.with_budget_share(100 - NEGATIVE_BUDGET_PERCENT),
+            ),
+            negatives: MemoryCacheBase::with_clock(
+                clock,
+                options.with_budget_share(NEGATIVE_BUDGET_PERCENT),
+            ),
+            stats: Counters::default(),
+        }
+    }
+
+    /// Caches the answers of a response on the CNAME chain of `question`, and
+    /// the response itself if it turns out negative.
+    pub fn insert_response(&self, question: &Question, response: &DnsPacket) {
+        if !matches!(
+            response.result_code(),
+            ResultCode::NoError | ResultCode::NameError
+        ) {
+            return;
+        }
+
+        // the answers of a NXDOMAIN response are the CNAMEs leading to the
+        // missing name
+        let (chain, unanswered) = answer_chain(question, response.answers());
+        self.insert_rrsets(&chain);
+
+        let Some(name) = unanswered else {
+            return;
+        };
+        let key = if response.result_code() == ResultCode::NameError {
+            NegativeKey::NameError(name, question.query_class())
+        } else {
+            NegativeKey::NoData(RRsetKey::new(
+                &name,
+                question.query_type(),
+                question.query_class(),
+            ))
+        };
+
+        // without a SOA there's no telling for how long the answer holds
+        let Some((soa, ttl)) = negative_soa(response) else {
+            return;
+        };
+        if ttl == 0 {
+            return;
+        }
+
+        self.negatives.add(
+            key,
+            soa.clone().with_ttl(ttl),
+            CacheItemPolicy::AbsoluteExpiration(Duration::from_secs(ttl as u64)),
+        );
+    }
+
+    /// Caches every RRset of `records` until the smallest TTL among its
+    /// records runs out.
+    pub fn insert_rrsets(&self, records: &[RawRecord]) {
+        let mut rrsets = FxHashMap::<RRsetKey, Vec<RawRecord>>::default();
+        for record in records {
+            let key = RRsetKey::new(record.name(), record.query_type(), record.query_class());
+            rrsets.entry(key).or_default().push(record.clone());
+        }
+
+        for (key, records) in rrsets {
+            let ttl = records.iter().map(RawRecord::ttl).min().unwrap_or_default();
+            if ttl == 0 {
+                continue;
+            }
+
+            self.rrsets.add(
+                key,
+                records,
+                CacheItemPolicy::AbsoluteExpiration(Duration::from_secs(ttl as u64)),
+            );
+        }
+    }
+
+    /// Answers `question` from cached RRsets and negative answers, following
+    /// cached CNAMEs. Returns `None` unless the whole chain up to the
+    /// requested type or a negative answer is cached.
+    pub fn lookup(&self, question: &Question) -> Option<CachedAnswer> {
+        let cached = self.lookup_with(question, None);
+
+        match &cached {
+            Some(cached) => {
+                self.stats.hits.fetch_add(1, Ordering::Relaxed);
+                if cached.prefetch {
+                    self.stats.prefetches.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            None => {
+                self.stats.misses.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        cached
+    }
+
+    /// Like `lookup`, but also uses expired entries within the stale window,
+    /// serving them with `stale_ttl` (RFC 8767).
+    pub fn lookup_stale(&self, question: &Question, stale_ttl: u32) -> Option<CachedAnswer> {
+        self.lookup_with(question, Some(stale_ttl))
+    }
+
+    /// Counts an answer from `lookup_stale` actually sent to a client.
+    pub fn count_stale_hit(&self) {
+        self.stats.stale_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Lets the entries answering `question` be flagged for prefetching
+    /// again, once prefetching them failed.
+    pub fn release_prefetch(&self, question: &Question) {
+        let query_class = question.query_class();
+        let mut name = question.name().clone();
+
+        for _ in 0..=MAX_CNAME_CHAIN_LENGTH {
+            let key = RRsetKey::new(&name, question.query_type(), query_class);
+            self.rrsets.release_prefetch(&key);
+            self.negatives
+                .release_prefetch(&NegativeKey::NameError(name.clone(), query_class));
+            self.negatives.release_prefetch(&NegativeKey::NoData(key));
+
+            let key = RRsetKey::new(&name, QueryType::CNAME, query_class);
+            let Some(RecordData::CNAME(target)) = self
+                .rrsets
+                .release_prefetch(&key)
+                .and_then(|records| records.first().map(|record| record.data().clone()))
+            else {
+                return;
+            };
+            name = target;
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.stats.hits.load(Ordering::Relaxed),
+            misses: self.stats.misses.load(Ordering::Relaxed),
+            stale_hits: self.stats.stale_hits.load(Ordering::Relaxed),
+            prefetches: self.stats.prefetches.load(Ordering::Relaxed),
+            entries: self.rrsets.len() + self.negatives.len(),
+            size_bytes: self.rrsets.size_bytes() + self.negatives.size_bytes(),
+        }
+    }
+
+    fn lookup_with(&self, question: &Question, stale_ttl: Option<u32>) -> Option<CachedAnswer> {
+        let rrset = |key: &RRsetKey| match stale_ttl {
+            Some(_) => self.rrsets.get_allow_stale(key),
+            None => self.rrsets.get_with_remaining(key),
+        };
+        let negative = |key: &NegativeKey| match stale_ttl {
+            Some(_) => self.negatives.get_allow_stale(key),
+            None => self.negatives.get_with_remaining(key),
+        };
+
+        let mut answers = Vec::new();
+        let mut prefetch = false;
+        let mut name = question.name().clone();
+
+        // one round per name of the longest allowed chain
+        for _ in 0..=MAX_CNAME_CHAIN_LENGTH {
+            let key = RRsetKey::new(&name, question.query_type(), question.query_class());
+            if let Some(hit) = rrset(&key) {
+                answers.extend(with_remaining_ttl(&hit.value, hit.remaining, stale_ttl));
+                return Some(CachedAnswer {
+                    result_code: ResultCode::NoError,
+                    answers,
+                    authorities: Vec::new(),
+                    prefetch: prefetch || hit.prefetch,
+                });
+            }
+
+            let name_error = NegativeKey::NameError(name.clone(), question.query_class());
+            let no_data = NegativeKey::NoData(key);
+            for (key, result_code) in [
+                (name_error, ResultCode::NameError),
+                (no_data, ResultCode::NoError),
+            ] {
+                if let Some(hit) = negative(&key) {
+                    let soa = hit.value.as_ref().clone();
+                    return Some(CachedAnswer {
+                        result_code,
+                        answers,
+                        authorities: with_remaining_ttl(&[soa], hit.remaining, stale_ttl),
+                        prefetch: prefetch || hit.prefetch,
+                    });
+                }
+            }
+
+            if question.query_type() == QueryType::CNAME {
+                return None;
+            }
+
+            let key = RRsetKey::new(&name, QueryType::CNAME, question.query_class());
+            let hit = rrset(&key)?;
+            let record = hit.value.first()?;
+            let RecordData::CNAME(target) = record.data() else {
+                return None;
+            };
+
+            name = target.clone();
+            answers.extend(with_remaining_ttl(
+                &hit.value[..1],
+                hit.remaining,
+                stale_ttl,
+            ));
+            prefetch |= hit.prefetch;
+        }
+
+        None
+    }
+}
+
+/// Follows the CNAMEs among `answers` from the question name. Returns the
+/// records owned by the names on the chain, and the name at its end unless
+/// records of the asked type are there.
+fn answer_chain(question: &Question, answers: &[RawRecord]) -> (Vec<RawRecord>, Option<Name>) {
+    let mut chain = Vec::<RawRecord>::new();
+    let mut name = question.name().clone();
+
+    for _ in 0..=MAX_CNAME_CHAIN_LENGTH {
+        // a CNAME loop
+        if chain.iter().any(|record| *record.name() == name) {
+            break;
+        }
+
+        let mut owned = answers.iter().filter(|record| *record.name() == name);
+        chain.extend(owned.clone().cloned());
+        if owned
+            .clone()
+            .any(|record| record.query_type() == question.query_type())
+        {
+            return (chain, None);
+        }
+
+        match owned.find_map(|record| match record.data() {
+            RecordData::CNAME(target) => Some(target),
+            _ => None,
+        }) {
+            Some(target) => name = target.clone(),
+            None => return (chain, Some(name)),
+        }
+    }
+
+    (chain, None)
+}
+
+/// The SOA record of a negative response and the TTL of the negative answer:
+/// the smaller of the record's TTL and the SOA minimum (RFC 2308 5).
+fn negative_soa(response: &DnsPacket) -> Option<(&RawRecord, u32)> {
+    response
+        .authorities()
+        .iter()
+        .find_map(|record| match record.data() {
+            RecordData::SOA { minimum, .. } => Some((record, record.ttl().min(*minimum))),
+            _ => None,
+        })
+}
+
+/// Entries expire with their smallest TTL, so the time left is the TTL of
+/// every record. Expired ones get `stale_ttl`.
+fn with_remaining_ttl(
+    records: &[RawRecord],
+    remaining: Duration,
+    stale_ttl: Option<u32>,
+) -> Vec<RawRecord> {
+    let ttl = match stale_ttl {
+        Some(stale_ttl) if remaining.is_zero() => stale_ttl,
+        _ => u32::try_from(remaining.as_secs()).unwrap_or(u32::MAX),
+    };
+
+    records
+        .iter()
+        .map(|record| record.clone().with_ttl(ttl))
+        .collect()
+}
@@ -0,0 +1,95 @@
+use rustc_hash::FxHashMap;
+use std::collections::BTreeMap;
+use std::hash::Hash;
+
+struct Entry<V> {
+    value: V,
+    size: usize,
+    /// Position in `order`, bumped on every use.
+    tick: u64,
+}
+
+/// Map bounded by the total size of its entries, evicting the least recently
+/// used ones to make room.
+pub(in crate::cache) struct Lru<K, V> {
+    entries: FxHashMap<K, Entry<V>>,
+    order: BTreeMap<u64, K>,
+    next_tick: u64,
+    size: usize,
+    max_size: usize,
+}
+
+impl<K: Eq + Hash + Clone, V> Lru<K, V> {
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            entries: FxHashMap::default(),
+            order: BTreeMap::new(),
+            next_tick: 0,
+            size: 0,
+            max_size,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.entries.iter().map(|(key, entry)| (key, &entry.value))
+    }
+
+    /// Marks the entry as the most recently used one.
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let entry = self.entries.get_mut(key)?;
+
+        let key = self.order.remove(&entry.tick).expect("entry out of order");
+        entry.tick = self.next_tick;
+        self.order.insert(entry.tick, key);
+        self.next_tick += 1;
+
+        Some(&mut entry.value)
+    }
+
+    /// Entries bigger than the whole budget are not kept at all.
+    pub fn insert(&mut self, key: K, value: V, size: usize) {
+        self.remove(&key);
+        if size > self.max_size {
+            return;
+        }
+
+        while self.size + size > self.max_size {
+            self.evict();
+        }
+
+        self.order.insert(self.next_tick, key.clone());
+        self.entries.insert(
+            key,
+            Entry {
+                value,
+                size,
+                tick: self.next_tick,
+            },
+        );
+        self.next_tick += 1;
+        self.size += size;
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let entry = self.entries.remove(key)?;
+        self.order.remove(&entry.tick);
+        self.size -= entry.size;
+
+        Some(entry.value)
+    }
+
+    fn evict(&mut self) {
+        if let Some((_, key)) = self.order.pop_first() {
+            let entry = self.entries.remove(&key).expect("ordered key missing");
+            self.size -= entry.size;
+        }
+    }
+}
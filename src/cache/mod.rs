@@ -1,25 +1,91 @@
+mod dns;
+mod lru;
+mod snapshot;
+
+pub use dns::DnsCache;
+#[cfg(test)]
+pub use dns::DnsCacheBase;
+
+use crate::cache::lru::Lru;
 use crate::helpers::{SystemTimeProvider, UnixTimeProvider};
+use crate::models::Name;
 use rustc_hash::FxHasher;
 use std::fmt::Debug;
-use std::hash::{BuildHasherDefault, Hash};
-use std::sync::Arc;
+use std::hash::{BuildHasher, BuildHasherDefault, Hash};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-type FxDashMap<K, V> = dashmap::DashMap<K, V, BuildHasherDefault<FxHasher>>;
+const DEFAULT_MAX_SIZE_BYTES: usize = 64 * 1024 * 1024;
+/// Items are spread over shards by key so lookups rarely contend.
+const SHARD_COUNT: usize = 16;
+/// Room for a few small entries per shard, whatever the budget.
+const MIN_SHARD_SIZE_BYTES: usize = 1024;
+const DEFAULT_PREFETCH_MIN_HITS: u64 = 3;
+
+#[derive(Clone, Debug)]
+pub struct CacheOptions {
+    /// Approximate memory the items may take, the least recently used ones
+    /// are evicted beyond it. Budgets too small to split between the shards
+    /// are rounded up.
+    pub max_size_bytes: usize,
+    /// How long expired items are kept around for `get_allow_stale`.
+    pub max_stale: Duration,
+    /// Items hit at least `prefetch_min_hits` times are flagged for
+    /// prefetching once in this last share of their lifetime, 0 disables it.
+    pub prefetch_threshold_percent: u8,
+    pub prefetch_min_hits: u64,
+}
+
+impl Default for CacheOptions {
+    fn default() -> Self {
+        Self {
+            max_size_bytes: DEFAULT_MAX_SIZE_BYTES,
+            max_stale: Duration::ZERO,
+            prefetch_threshold_percent: 0,
+            prefetch_min_hits: DEFAULT_PREFETCH_MIN_HITS,
+        }
+    }
+}
+
+impl CacheOptions {
+    /// The options for one of several caches splitting the memory budget,
+    /// getting `percent` of it.
+    pub fn with_budget_share(&self, percent: usize) -> Self {
+        Self {
+            max_size_bytes: self.max_size_bytes / 100 * percent,
+            ..self.clone()
+        }
+    }
+}
+
+/// Approximate memory taken by a cached key or value, heap included.
+pub trait CacheSize {
+    fn cache_size(&self) -> usize;
+}
+
+impl CacheSize for Name {
+    fn cache_size(&self) -> usize {
+        std::mem::size_of::<Self>() + self.wire_len()
+    }
+}
 
-const MAX_CACHE_SIZE: usize = 2_097_152;
-const DROP_UNUSED_PERIOD_SECS: u64 = 60 * 60;
+impl<T: CacheSize> CacheSize for Vec<T> {
+    fn cache_size(&self) -> usize {
+        std::mem::size_of::<Self>() + self.iter().map(CacheSize::cache_size).sum::<usize>()
+    }
+}
 
 pub enum CacheItemPolicy {
     AbsoluteExpiration(Duration),
-    NoExpiration,
 }
 
 struct CacheItem<V: Debug> {
     pub value: Arc<V>,
     pub policy: CacheItemPolicy,
     pub created: u64,
-    pub last_used: u64,
+    pub hits: u64,
+    /// Whether a hit was already flagged for prefetching.
+    pub prefetch_claimed: bool,
 }
 
 impl<V: Debug> CacheItem<V> {
@@ -28,73 +94,213 @@ impl<V: Debug> CacheItem<V> {
             value: Arc::new(value),
             policy,
             created,
-            last_used: created,
+            hits: 0,
+            prefetch_claimed: false,
         }
     }
 }
 
+/// An item found in the cache.
+pub struct CacheHit<V> {
+    pub value: Arc<V>,
+    /// Zero for stale items.
+    pub remaining: Duration,
+    /// Set on a single hit of a popular item close to expiring, the one
+    /// which should refresh it.
+    pub prefetch: bool,
+}
+
+/// A fresh item as saved to a snapshot.
+pub struct StoredItem<K, V> {
+    pub key: K,
+    pub value: Arc<V>,
+    /// Unix time the item was added at.
+    pub created: u64,
+    pub lifetime: Duration,
+}
+
+type Shard<K, V> = Mutex<Lru<K, CacheItem<V>>>;
+
 pub type MemoryCache<K, V> = MemoryCacheBase<K, V, SystemTimeProvider>;
 
-impl<K: Eq + Hash + Debug, V: Debug> MemoryCache<K, V> {
-    pub fn new() -> Self {
-        Self::with_clock(SystemTimeProvider)
+impl<K: Eq + Hash + Clone + Debug + CacheSize, V: Debug + CacheSize> MemoryCache<K, V> {
+    pub fn new(options: CacheOptions) -> Self {
+        Self::with_clock(SystemTimeProvider, options)
     }
 }
 
-pub struct MemoryCacheBase<K: Eq + Hash + Debug, V: Debug, T: UnixTimeProvider> {
+/// Expired items are dropped when next looked up or evicted like any other
+/// unused item, never by scanning the whole cache.
+pub struct MemoryCacheBase<K, V: Debug, T: UnixTimeProvider> {
     clock: T,
-    cache: FxDashMap<K, CacheItem<V>>,
+    options: CacheOptions,
+    hasher: BuildHasherDefault<FxHasher>,
+    shards: Box<[Shard<K, V>]>,
 }
 
-impl<K: Eq + Hash + Debug, V: Debug, T: UnixTimeProvider> MemoryCacheBase<K, V, T> {
-    pub fn with_clock(clock: T) -> Self {
+impl<K: Eq + Hash + Clone + Debug + CacheSize, V: Debug + CacheSize, T: UnixTimeProvider>
+    MemoryCacheBase<K, V, T>
+{
+    pub fn with_clock(clock: T, options: CacheOptions) -> Self {
+        let shard_size = (options.max_size_bytes / SHARD_COUNT).max(MIN_SHARD_SIZE_BYTES);
+
         Self {
             clock,
-            cache: FxDashMap::default(),
+            options,
+            hasher: BuildHasherDefault::default(),
+            shards: (0..SHARD_COUNT)
+                .map(|_| Mutex::new(Lru::new(shard_size)))
+                .collect(),
         }
     }
 
     pub fn add(&self, key: K, value: V, policy: CacheItemPolicy) {
-        self.drop_expired();
+        self.insert(key, value, policy, self.clock.unix_time_as_secs());
+    }
 
-        if self.cache.len() >= MAX_CACHE_SIZE {
-            self.drop_unused_for(DROP_UNUSED_PERIOD_SECS);
+    /// Adds an item saved to a snapshot unless it expired since. Returns
+    /// whether it was added.
+    pub fn restore(&self, key: K, value: V, created: u64, policy: CacheItemPolicy) -> bool {
+        let item = CacheItem::new(value, policy, created);
+        if self.expires(&item) <= self.clock.unix_time_as_secs() {
+            return false;
         }
 
-        self.cache.insert(
-            key,
-            CacheItem::new(value, policy, self.clock.unix_time_as_secs()),
-        );
+        self.insert_item(key, item);
+        true
+    }
+
+    /// The items which haven't expired yet.
+    pub fn fresh_items(&self) -> Vec<StoredItem<K, V>> {
+        let now = self.clock.unix_time_as_secs();
+
+        let mut items = Vec::new();
+        for shard in self.shards.iter() {
+            let shard = shard.lock().unwrap();
+            for (key, item) in shard.iter() {
+                if self.expires(item) <= now {
+                    continue;
+                }
+                let CacheItemPolicy::AbsoluteExpiration(lifetime) = item.policy;
+                items.push(StoredItem {
+                    key: key.clone(),
+                    value: Arc::clone(&item.value),
+                    created: item.created,
+                    lifetime,
+                });
+            }
+        }
+
+        items
     }
 
     pub fn get(&self, key: &K) -> Option<Arc<V>> {
-        self.cache.remove_if(key, |_, item| !self.is_valid(item));
-        self.cache.get_mut(key).map(|mut item| {
-            item.last_used = self.clock.unix_time_as_secs();
-            Arc::clone(&item.value)
+        self.get_item(key, false).map(|hit| hit.value)
+    }
+
+    /// Like `get`, along with how long the item has left to live and whether
+    /// it's time to prefetch it.
+    pub fn get_with_remaining(&self, key: &K) -> Option<CacheHit<V>> {
+        self.get_item(key, false)
+    }
+
+    /// Like `get_with_remaining`, but also returns items expired less than
+    /// `max_stale` ago, with no time left. Never flags items for prefetching.
+    pub fn get_allow_stale(&self, key: &K) -> Option<CacheHit<V>> {
+        self.get_item(key, true)
+    }
+
+    /// Lets the item be flagged for prefetching again, returning its value.
+    pub fn release_prefetch(&self, key: &K) -> Option<Arc<V>> {
+        let mut shard = self.shard(key).lock().unwrap();
+        let item = shard.get_mut(key)?;
+        item.prefetch_claimed = false;
+
+        Some(Arc::clone(&item.value))
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().len())
+            .sum()
+    }
+
+    /// Approximate memory taken by the items.
+    pub fn size_bytes(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().size())
+            .sum()
+    }
+
+    fn insert(&self, key: K, value: V, policy: CacheItemPolicy, created: u64) {
+        self.insert_item(key, CacheItem::new(value, policy, created));
+    }
+
+    fn insert_item(&self, key: K, item: CacheItem<V>) {
+        // the key is stored twice, in the map and in the usage order
+        let size =
+            2 * key.cache_size() + item.value.cache_size() + std::mem::size_of::<CacheItem<V>>();
+
+        self.shard(&key).lock().unwrap().insert(key, item, size);
+    }
+
+    fn shard(&self, key: &K) -> &Shard<K, V> {
+        &self.shards[self.hasher.hash_one(key) as usize % SHARD_COUNT]
+    }
+
+    fn get_item(&self, key: &K, allow_stale: bool) -> Option<CacheHit<V>> {
+        let now = self.clock.unix_time_as_secs();
+
+        let mut shard = self.shard(key).lock().unwrap();
+        let item = shard.get_mut(key)?;
+        if !self.is_retained(item, now) {
+            shard.remove(key);
+            return None;
+        }
+
+        let remaining = self.remaining(item, now);
+        if remaining.is_zero() && !allow_stale {
+            return None;
+        }
+
+        item.hits += 1;
+        let prefetch = !allow_stale && self.is_due_for_prefetch(item, remaining);
+        if prefetch {
+            item.prefetch_claimed = true;
+        }
+
+        Some(CacheHit {
+            value: Arc::clone(&item.value),
+            remaining,
+            prefetch,
         })
     }
 
-    fn drop_expired(&self) {
-        self.cache.retain(|_, item| self.is_valid(item));
+    fn is_due_for_prefetch(&self, item: &CacheItem<V>, remaining: Duration) -> bool {
+        let CacheItemPolicy::AbsoluteExpiration(lifetime) = item.policy;
+
+        self.options.prefetch_threshold_percent > 0
+            && !item.prefetch_claimed
+            && item.hits >= self.options.prefetch_min_hits
+            && !remaining.is_zero()
+            && remaining.as_secs() * 100
+                <= lifetime.as_secs() * self.options.prefetch_threshold_percent as u64
     }
 
-    fn is_valid(&self, item: &CacheItem<V>) -> bool {
+    fn expires(&self, item: &CacheItem<V>) -> u64 {
         match item.policy {
-            CacheItemPolicy::NoExpiration => true,
-            CacheItemPolicy::AbsoluteExpiration(duration) => {
-                let (created, duration, now) = (
-                    item.created,
-                    duration.as_secs(),
-                    self.clock.unix_time_as_secs(),
-                );
-                created + duration > now
-            }
+            CacheItemPolicy::AbsoluteExpiration(duration) => item.created + duration.as_secs(),
         }
     }
 
-    fn drop_unused_for(&self, period: u64) {
-        let now = self.clock.unix_time_as_secs();
-        self.cache.retain(|_, item| item.last_used + period > now);
+    fn remaining(&self, item: &CacheItem<V>, now: u64) -> Duration {
+        Duration::from_secs(self.expires(item).saturating_sub(now))
+    }
+
+    /// Whether the item is still fresh or may be served stale.
+    fn is_retained(&self, item: &CacheItem<V>, now: u64) -> bool {
+        self.expires(item) + self.options.max_stale.as_secs() > now
     }
 }
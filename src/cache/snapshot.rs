@@ -0,0 +1,175 @@
+use crate::cache::dns::{DnsCacheBase, NegativeKey, RRsetKey};
+use crate::cache::CacheItemPolicy;
+use crate::helpers::UnixTimeProvider;
+use crate::models::{new_packet_buffer, DnsPacket, DnsPacketBuilder, Question};
+use crate::smart_buffer::SmartBuffer;
+use anyhow::{bail, Context, Result};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+const MAGIC: &[u8] = b"DNSCACHE";
+const VERSION: u8 = 1;
+
+const RRSET: u8 = 0;
+const NAME_ERROR: u8 = 1;
+const NO_DATA: u8 = 2;
+
+/// Snapshots are a header followed by one entry per cache item: its kind, the
+/// unix time it was cached at, its lifetime and a DNS message holding the key
+/// as question and the records.
+impl<T: UnixTimeProvider> DnsCacheBase<T> {
+    /// Saves the entries which haven't expired yet. The snapshot is written
+    /// next to `path` and renamed over it, so neither a crash nor another
+    /// save can leave a partial file behind. Returns how many entries were
+    /// saved.
+    pub fn save_snapshot(&self, path: &Path) -> Result<usize> {
+        let mut data = MAGIC.to_vec();
+        data.push(VERSION);
+        let mut buf = new_packet_buffer(u16::MAX);
+        let mut count = 0;
+
+        for item in self.rrsets.fresh_items() {
+            let key = &item.key;
+            let packet = DnsPacketBuilder::default()
+                .with_question(Question::new(
+                    key.name.clone(),
+                    key.query_type,
+                    key.query_class,
+                ))
+                .with_answers(item.value.iter().cloned())
+                .build();
+
+            let len = packet.to_bytes(&mut buf)?;
+            write_entry(&mut data, RRSET, item.created, item.lifetime, &buf[..len]);
+            count += 1;
+        }
+
+        for item in self.negatives.fresh_items() {
+            let soa = item.value.as_ref();
+            let (kind, question) = match &item.key {
+                NegativeKey::NameError(name, query_class) => (
+                    NAME_ERROR,
+                    Question::new(name.clone(), soa.query_type(), *query_class),
+                ),
+                NegativeKey::NoData(key) => (
+                    NO_DATA,
+                    Question::new(key.name.clone(), key.query_type, key.query_class),
+                ),
+            };
+            let packet = DnsPacketBuilder::default()
+                .with_question(question)
+                .with_authorities([soa.clone()])
+                .build();
+
+            let len = packet.to_bytes(&mut buf)?;
+            write_entry(&mut data, kind, item.created, item.lifetime, &buf[..len]);
+            count += 1;
+        }
+
+        write_atomically(path, &data)
+            .with_context(|| format!("failed writing cache snapshot {}", path.display()))?;
+
+        Ok(count)
+    }
+
+    /// Restores the entries of a snapshot which haven't expired since it was
+    /// saved. Returns how many were restored.
+    pub fn load_snapshot(&self, path: &Path) -> Result<usize> {
+        let data = fs::read(path)
+            .with_context(|| format!("failed reading cache snapshot {}", path.display()))?;
+
+        let mut smart_buf = SmartBuffer::new(&data);
+        if smart_buf.read_slice(MAGIC.len()).ok() != Some(MAGIC)
+            || smart_buf.read_u8().ok() != Some(VERSION)
+        {
+            bail!("{} is not a cache snapshot", path.display());
+        }
+
+        let mut count = 0;
+        while smart_buf.pos() < data.len() {
+            let kind = smart_buf.read_u8()?;
+            let created = u64::from(smart_buf.read_u32()?) << 32 | u64::from(smart_buf.read_u32()?);
+            let lifetime = Duration::from_secs(smart_buf.read_u32()? as u64);
+            let len = smart_buf.read_u16()?;
+            let packet = DnsPacket::from_bytes(smart_buf.read_slice(len as usize)?)?;
+
+            // RRsets too big for a single message are left out
+            if packet.is_truncated() {
+                continue;
+            }
+            let Some(question) = packet.questions().first() else {
+                bail!("cache snapshot entry without a key");
+            };
+            let policy = CacheItemPolicy::AbsoluteExpiration(lifetime);
+
+            let restored = match kind {
+                RRSET => self.rrsets.restore(
+                    RRsetKey::new(
+                        question.name(),
+                        question.query_type(),
+                        question.query_class(),
+                    ),
+                    packet.answers().to_vec(),
+                    created,
+                    policy,
+                ),
+                NAME_ERROR | NO_DATA => {
+                    let Some(soa) = packet.authorities().first() else {
+                        bail!("negative cache snapshot entry without a SOA record");
+                    };
+                    let key = if kind == NAME_ERROR {
+                        NegativeKey::NameError(question.name().clone(), question.query_class())
+                    } else {
+                        NegativeKey::NoData(RRsetKey::new(
+                            question.name(),
+                            question.query_type(),
+                            question.query_class(),
+                        ))
+                    };
+                    self.negatives.restore(key, soa.clone(), created, policy)
+                }
+                kind => bail!("unknown cache snapshot entry kind {kind}"),
+            };
+            count += restored as usize;
+        }
+
+        Ok(count)
+    }
+}
+
+fn write_entry(data: &mut Vec<u8>, kind: u8, created: u64, lifetime: Duration, packet: &[u8]) {
+    data.push(kind);
+    data.extend_from_slice(&created.to_be_bytes());
+    data.extend_from_slice(&(lifetime.as_secs() as u32).to_be_bytes());
+    data.extend_from_slice(&(packet.len() as u16).to_be_bytes());
+    data.extend_from_slice(packet);
+}
+
+fn write_atomically(path: &Path, data: &[u8]) -> Result<()> {
+    // each save gets its own file, so concurrent ones don't clobber it
+    static SAVES: AtomicU64 = AtomicU64::new(0);
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(format!(
+        ".{}.{}.tmp",
+        std::process::id(),
+        SAVES.fetch_add(1, Ordering::Relaxed)
+    ));
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&tmp_path)?;
+    let written = file
+        .write_all(data)
+        .and_then(|_| file.sync_all())
+        .and_then(|_| fs::rename(&tmp_path, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+
+    Ok(written?)
+}
@@ -0,0 +1,105 @@
+use crate::config::{
+    parse_forward, parse_socket_addr, parse_zone, Config, ForwardConfig, ZoneConfig,
+};
+use clap::Parser;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+/// Handmade DNS server.
+///
+/// Every flag overrides the corresponding value of the config file; list
+/// flags replace the whole list.
+#[derive(Debug, Parser)]
+#[command(version)]
+pub(in crate::config) struct Cli {
+    /// Path to the TOML configuration file
+    #[arg(short, long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Address to listen on (may be repeated)
+    #[arg(short, long, value_name = "ADDR", value_parser = parse_socket_addr)]
+    pub listen: Vec<SocketAddr>,
+
+    /// Upstream server to forward queries to (may be repeated)
+    #[arg(short, long, value_name = "ADDR", value_parser = parse_socket_addr)]
+    pub upstream: Vec<SocketAddr>,
+
+    /// How long to wait for an upstream response
+    #[arg(long, value_name = "MS")]
+    pub upstream_timeout: Option<u64>,
+
+    /// How many times to resend a query an upstream didn't answer
+    #[arg(long, value_name = "N")]
+    pub upstream_retries: Option<usize>,
+
+    /// Zone file to serve, optionally prefixed with its origin (may be repeated)
+    #[arg(short, long, value_name = "[ORIGIN=]FILE", value_parser = parse_zone)]
+    pub zone: Vec<ZoneConfig>,
+
+    /// Forward names under SUFFIX to their own servers (may be repeated)
+    #[arg(short, long, value_name = "SUFFIX=ADDR[,ADDR...]", value_parser = parse_forward)]
+    pub forward: Vec<ForwardConfig>,
+
+    /// Resolve names from the root servers instead of forwarding them
+    #[arg(short, long)]
+    pub recursive: bool,
+
+    /// Number of worker threads
+    #[arg(short, long, value_name = "N")]
+    pub workers: Option<usize>,
+
+    /// Approximate memory the cached responses may take
+    #[arg(long, value_name = "BYTES")]
+    pub cache_max_size: Option<usize>,
+
+    /// Answer from expired cache entries when the upstreams fail
+    #[arg(long)]
+    pub serve_stale: bool,
+
+    /// Save the cache to this file and restore it on startup
+    #[arg(long, value_name = "FILE")]
+    pub cache_snapshot: Option<PathBuf>,
+}
+
+impl Cli {
+    pub(in crate::config) fn apply(self, config: &mut Config) {
+        if !self.listen.is_empty() {
+            config.server.listen = self.listen;
+        }
+        if self.workers.is_some() {
+            config.server.workers = self.workers;
+        }
+
+        if !self.upstream.is_empty() {
+            config.upstream.servers = self.upstream;
+        }
+        if let Some(timeout) = self.upstream_timeout {
+            config.upstream.timeout_ms = timeout;
+        }
+        if let Some(retries) = self.upstream_retries {
+            config.upstream.retries = retries;
+        }
+
+        if !self.zone.is_empty() {
+            config.zones = self.zone;
+        }
+
+        if !self.forward.is_empty() {
+            config.forward = self.forward;
+        }
+
+        if self.recursive {
+            config.recursion.enabled = true;
+        }
+
+        if let Some(max_size) = self.cache_max_size {
+            config.cache.max_size_bytes = max_size;
+        }
+        if self.serve_stale {
+            config.cache.serve_stale = true;
+        }
+        if self.cache_snapshot.is_some() {
+            config.cache.snapshot_file = self.cache_snapshot;
+        }
+    }
+}
@@ -0,0 +1,491 @@
+mod cli;
+
+use crate::cache::CacheOptions;
+use crate::models::{Name, DEFAULT_UDP_PAYLOAD_SIZE, MIN_UDP_PAYLOAD_SIZE};
+use crate::server::{
+    ForwarderOptions, HealthOptions, RecursorOptions, ServeStaleOptions, TcpOptions,
+};
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use cli::Cli;
+use serde::{Deserialize, Deserializer};
+use std::collections::HashSet;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const DEFAULT_DNS_PORT: u16 = 53;
+
+const DEFAULT_ZONE_FILE: &str = "bind.txt";
+
+const DEFAULT_STATS_INTERVAL_SECS: u64 = 300;
+
+/// RFC 8767 suggests one to three days.
+const DEFAULT_MAX_STALE_SECS: u64 = 24 * 60 * 60;
+const DEFAULT_PREFETCH_THRESHOLD_PERCENT: u8 = 10;
+const DEFAULT_SNAPSHOT_INTERVAL_SECS: u64 = 300;
+
+/// IPv4 addresses of a.root-servers.net to m.root-servers.net.
+const ROOT_HINTS: [&str; 13] = [
+    "198.41.0.4",
+    "170.247.170.2",
+    "192.33.4.12",
+    "199.7.91.13",
+    "192.203.230.10",
+    "192.5.5.241",
+    "192.112.36.4",
+    "198.97.190.53",
+    "192.36.148.17",
+    "192.58.128.30",
+    "193.0.14.129",
+    "199.7.83.42",
+    "202.12.27.33",
+];
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub upstream: UpstreamConfig,
+    pub zones: Vec<ZoneConfig>,
+    pub forward: Vec<ForwardConfig>,
+    pub recursion: RecursionConfig,
+    pub cache: CacheConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            server: ServerConfig::default(),
+            upstream: UpstreamConfig::default(),
+            zones: vec![ZoneConfig {
+                origin: None,
+                file: PathBuf::from(DEFAULT_ZONE_FILE),
+            }],
+            forward: Vec::new(),
+            recursion: RecursionConfig::default(),
+            cache: CacheConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    #[serde(deserialize_with = "deserialize_socket_addrs")]
+    pub listen: Vec<SocketAddr>,
+    pub workers: Option<usize>,
+    /// Largest UDP response, whatever bigger size clients advertise.
+    pub max_udp_payload_size: u16,
+    pub tcp_idle_timeout_secs: u64,
+    pub tcp_max_connections_per_client: usize,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        let tcp_defaults = TcpOptions::default();
+
+        Self {
+            listen: vec![SocketAddr::from(([0, 0, 0, 0], DEFAULT_DNS_PORT))],
+            workers: None,
+            max_udp_payload_size: DEFAULT_UDP_PAYLOAD_SIZE,
+            tcp_idle_timeout_secs: tcp_defaults.idle_timeout.as_secs(),
+            tcp_max_connections_per_client: tcp_defaults.max_connections_per_client,
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn tcp_options(&self) -> TcpOptions {
+        TcpOptions {
+            idle_timeout: Duration::from_secs(self.tcp_idle_timeout_secs),
+            max_connections_per_client: self.tcp_max_connections_per_client,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UpstreamConfig {
+    #[serde(deserialize_with = "deserialize_socket_addrs")]
+    pub servers: Vec<SocketAddr>,
+    pub timeout_ms: u64,
+    pub retries: usize,
+    pub socket_pool_size: usize,
+    /// Consecutive failures (timeouts or SERVFAIL) taking a server out of
+    /// rotation.
+    pub max_failures: u32,
+    pub cooldown_secs: u64,
+    /// How often per-upstream statistics are logged, 0 disables it.
+    pub stats_interval_secs: u64,
+}
+
+impl Default for UpstreamConfig {
+    fn default() -> Self {
+        let defaults = ForwarderOptions::default();
+        let health_defaults = HealthOptions::default();
+
+        Self {
+            servers: vec![SocketAddr::from(([8, 8, 8, 8], DEFAULT_DNS_PORT))],
+            timeout_ms: defaults.timeout.as_millis() as u64,
+            retries: defaults.retries,
+            socket_pool_size: defaults.socket_pool_size,
+            max_failures: health_defaults.max_failures,
+            cooldown_secs: health_defaults.cooldown.as_secs(),
+            stats_interval_secs: DEFAULT_STATS_INTERVAL_SECS,
+        }
+    }
+}
+
+impl UpstreamConfig {
+    pub fn forwarder_options(&self) -> ForwarderOptions {
+        ForwarderOptions {
+            timeout: Duration::from_millis(self.timeout_ms),
+            retries: self.retries,
+            socket_pool_size: self.socket_pool_size,
+            ..ForwarderOptions::default()
+        }
+    }
+
+    pub fn health_options(&self) -> HealthOptions {
+        HealthOptions {
+            max_failures: self.max_failures,
+            cooldown: Duration::from_secs(self.cooldown_secs),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ZoneConfig {
+    /// Initial `$ORIGIN` of the file, relative names are resolved against
+    /// the root when it's not set.
+    pub origin: Option<String>,
+    pub file: PathBuf,
+}
+
+/// Forwards names under `suffix` to their own upstream servers.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ForwardConfig {
+    pub suffix: String,
+    #[serde(deserialize_with = "deserialize_socket_addrs")]
+    pub servers: Vec<SocketAddr>,
+    /// Falls back to `upstream.timeout_ms`.
+    pub timeout_ms: Option<u64>,
+}
+
+impl ForwardConfig {
+    pub fn forwarder_options(&self, upstream: &UpstreamConfig) -> ForwarderOptions {
+        let mut options = upstream.forwarder_options();
+        if let Some(timeout_ms) = self.timeout_ms {
+            options.timeout = Duration::from_millis(timeout_ms);
+        }
+        options
+    }
+}
+
+/// Resolves names iteratively from the root instead of forwarding them to
+/// `upstream.servers`. Forwarding rules still apply.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RecursionConfig {
+    pub enabled: bool,
+    #[serde(deserialize_with = "deserialize_socket_addrs")]
+    pub root_hints: Vec<SocketAddr>,
+    /// Port of the authoritative servers learned from referrals.
+    pub query_port: u16,
+    pub max_depth: usize,
+    pub query_budget: usize,
+    pub timeout_ms: u64,
+}
+
+impl Default for RecursionConfig {
+    fn default() -> Self {
+        let defaults = RecursorOptions::default();
+
+        Self {
+            enabled: false,
+            root_hints: ROOT_HINTS
+                .iter()
+                .map(|ip| SocketAddr::new(ip.parse().unwrap(), DEFAULT_DNS_PORT))
+                .collect(),
+            query_port: defaults.query_port,
+            max_depth: defaults.max_depth,
+            query_budget: defaults.query_budget,
+            timeout_ms: defaults.timeout.as_millis() as u64,
+        }
+    }
+}
+
+impl RecursionConfig {
+    pub fn options(&self) -> RecursorOptions {
+        RecursorOptions {
+            root_hints: self.root_hints.clone(),
+            query_port: self.query_port,
+            max_depth: self.max_depth,
+            query_budget: self.query_budget,
+            timeout: Duration::from_millis(self.timeout_ms),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// Approximate memory the cached answers may take.
+    pub max_size_bytes: usize,
+    /// Answer from expired entries when the upstreams fail or are slow.
+    pub serve_stale: bool,
+    /// How long after expiring entries may still be served.
+    pub max_stale_secs: u64,
+    pub stale_answer_ttl_secs: u32,
+    /// How long to wait for the upstreams before answering from stale data.
+    pub client_response_timeout_ms: u64,
+    /// Refresh entries hit at least `prefetch_min_hits` times once they are
+    /// in this last share of their TTL, 0 disables prefetching.
+    pub prefetch_threshold_percent: u8,
+    pub prefetch_min_hits: u64,
+    /// Where the cache is saved on shutdown and every
+    /// `snapshot_interval_secs`, and restored from on startup.
+    pub snapshot_file: Option<PathBuf>,
+    pub snapshot_interval_secs: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        let defaults = CacheOptions::default();
+        let stale_defaults = ServeStaleOptions::default();
+
+        Self {
+            max_size_bytes: defaults.max_size_bytes,
+            serve_stale: false,
+            max_stale_secs: DEFAULT_MAX_STALE_SECS,
+            stale_answer_ttl_secs: stale_defaults.answer_ttl,
+            client_response_timeout_ms: stale_defaults.client_response_timeout.as_millis() as u64,
+            prefetch_threshold_percent: DEFAULT_PREFETCH_THRESHOLD_PERCENT,
+            prefetch_min_hits: defaults.prefetch_min_hits,
+            snapshot_file: None,
+            snapshot_interval_secs: DEFAULT_SNAPSHOT_INTERVAL_SECS,
+        }
+    }
+}
+
+impl CacheConfig {
+    pub fn options(&self) -> CacheOptions {
+        CacheOptions {
+            max_size_bytes: self.max_size_bytes,
+            max_stale: if self.serve_stale {
+                Duration::from_secs(self.max_stale_secs)
+            } else {
+                Duration::ZERO
+            },
+            prefetch_threshold_percent: self.prefetch_threshold_percent,
+            prefetch_min_hits: self.prefetch_min_hits,
+        }
+    }
+
+    pub fn serve_stale_options(&self) -> Option<ServeStaleOptions> {
+        self.serve_stale.then(|| ServeStaleOptions {
+            answer_ttl: self.stale_answer_ttl_secs,
+            client_response_timeout: Duration::from_millis(self.client_response_timeout_ms),
+        })
+    }
+}
+
+impl Config {
+    /// Builds the configuration from the command line: reads the file passed
+    /// with `--config` (if any), applies the remaining flags on top of it and
+    /// validates the result.
+    pub fn load() -> Result<Self> {
+        let cli = Cli::parse();
+
+        let mut config = match &cli.config {
+            Some(path) => Self::from_file(path)?,
+            None => Self::default(),
+        };
+        cli.apply(&mut config);
+
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed reading config file {}", path.display()))?;
+
+        Self::from_toml(&text).with_context(|| format!("invalid config file {}", path.display()))
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self> {
+        Ok(toml::from_str(text)?)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.server.listen.is_empty() {
+            bail!("server.listen: at least one listen address is required");
+        }
+        if let Some(addr) = first_duplicate(&self.server.listen) {
+            bail!("server.listen: duplicate listen address {addr}");
+        }
+        if self.server.workers == Some(0) {
+            bail!("server.workers: must be greater than 0");
+        }
+        if self.server.max_udp_payload_size < MIN_UDP_PAYLOAD_SIZE {
+            bail!("server.max_udp_payload_size: must be at least {MIN_UDP_PAYLOAD_SIZE}");
+        }
+        if self.server.tcp_idle_timeout_secs == 0 {
+            bail!("server.tcp_idle_timeout_secs: must be greater than 0");
+        }
+        if self.server.tcp_max_connections_per_client == 0 {
+            bail!("server.tcp_max_connections_per_client: must be greater than 0");
+        }
+
+        if self.upstream.servers.is_empty() && !self.recursion.enabled {
+            bail!("upstream.servers: at least one upstream server is required");
+        }
+        if let Some(addr) = first_duplicate(&self.upstream.servers) {
+            bail!("upstream.servers: duplicate upstream server {addr}");
+        }
+        if self.upstream.timeout_ms == 0 {
+            bail!("upstream.timeout_ms: must be greater than 0");
+        }
+        if self.upstream.socket_pool_size == 0 {
+            bail!("upstream.socket_pool_size: must be greater than 0");
+        }
+        if self.upstream.max_failures == 0 {
+            bail!("upstream.max_failures: must be greater than 0");
+        }
+
+        for zone in &self.zones {
+            if !zone.file.is_file() {
+                bail!("zones: zone file {} does not exist", zone.file.display());
+            }
+        }
+
+        let mut suffixes = HashSet::with_capacity(self.forward.len());
+        for rule in &self.forward {
+            let suffix = rule
+                .suffix
+                .parse::<Name>()
+                .with_context(|| format!("forward: invalid suffix '{}'", rule.suffix))?;
+            if suffix.is_root() {
+                bail!("forward: the root can't be a suffix, use upstream.servers instead");
+            }
+            if !suffixes.insert(suffix) {
+                bail!("forward: duplicate suffix {}", rule.suffix);
+            }
+            if rule.servers.is_empty() {
+                bail!("forward: no servers for suffix {}", rule.suffix);
+            }
+            if rule.timeout_ms == Some(0) {
+                bail!(
+                    "forward: timeout_ms for suffix {} must be greater than 0",
+                    rule.suffix
+                );
+            }
+        }
+
+        if self.recursion.enabled {
+            if self.recursion.root_hints.is_empty() {
+                bail!("recursion.root_hints: at least one root server is required");
+            }
+            if self.recursion.query_budget == 0 {
+                bail!("recursion.query_budget: must be greater than 0");
+            }
+            if self.recursion.timeout_ms == 0 {
+                bail!("recursion.timeout_ms: must be greater than 0");
+            }
+        }
+
+        if self.cache.max_size_bytes == 0 {
+            bail!("cache.max_size_bytes: must be greater than 0");
+        }
+        if self.cache.serve_stale {
+            if self.cache.max_stale_secs == 0 {
+                bail!("cache.max_stale_secs: must be greater than 0");
+            }
+            if self.cache.client_response_timeout_ms == 0 {
+                bail!("cache.client_response_timeout_ms: must be greater than 0");
+            }
+        }
+        if self.cache.prefetch_threshold_percent > 100 {
+            bail!("cache.prefetch_threshold_percent: must be at most 100");
+        }
+        if self.cache.snapshot_file.is_some() && self.cache.snapshot_interval_secs == 0 {
+            bail!("cache.snapshot_interval_secs: must be greater than 0");
+        }
+
+        Ok(())
+    }
+
+    pub fn workers(&self) -> usize {
+        self.server.workers.unwrap_or_else(|| {
+            std::thread::available_parallelism()
+                .expect("failed getting cpu number")
+                .get()
+        })
+    }
+}
+
+/// Parses `FILE` or `ORIGIN=FILE`.
+pub fn parse_zone(value: &str) -> Result<ZoneConfig> {
+    let (origin, file) = match value.split_once('=') {
+        Some((origin, file)) => (Some(origin.to_string()), file),
+        None => (None, value),
+    };
+
+    if file.is_empty() {
+        bail!("empty zone file path in '{value}'");
+    }
+
+    Ok(ZoneConfig {
+        origin,
+        file: PathBuf::from(file),
+    })
+}
+
+/// Parses `SUFFIX=ADDR[,ADDR...]`.
+pub fn parse_forward(value: &str) -> Result<ForwardConfig> {
+    let Some((suffix, servers)) = value.split_once('=') else {
+        bail!("expected SUFFIX=ADDR[,ADDR...], got '{value}'");
+    };
+
+    Ok(ForwardConfig {
+        suffix: suffix.to_string(),
+        servers: servers
+            .split(',')
+            .map(parse_socket_addr)
+            .collect::<Result<_>>()?,
+        timeout_ms: None,
+    })
+}
+
+/// Parses `ip`, `ip:port` or `[ipv6]:port`, falling back to the DNS port.
+pub fn parse_socket_addr(value: &str) -> Result<SocketAddr> {
+    if let Ok(addr) = value.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+
+    match value.parse::<IpAddr>() {
+        Ok(ip) => Ok(SocketAddr::new(ip, DEFAULT_DNS_PORT)),
+        Err(_) => bail!("invalid socket address '{value}'"),
+    }
+}
+
+fn deserialize_socket_addrs<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Vec<SocketAddr>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|value| parse_socket_addr(value).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+fn first_duplicate(addrs: &[SocketAddr]) -> Option<SocketAddr> {
+    let mut seen = HashSet::with_capacity(addrs.len());
+    addrs.iter().find(|addr| !seen.insert(**addr)).copied()
+}
@@ -1,10 +1,12 @@
 // TODO: remove anyhow
 
 mod cache;
+mod config;
 mod helpers;
 mod models;
 mod server;
 mod smart_buffer;
+mod zone;
 
 #[cfg(test)]
 mod tests;
@@ -12,12 +14,16 @@ mod tests;
 fn main() {
     env_logger::init();
 
-    server::DnsServer::new()
+    let config = match config::Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("error: {e:#}");
+            std::process::exit(1);
+        }
+    };
+
+    server::DnsServer::new(&config)
         .unwrap()
-        .run(
-            std::thread::available_parallelism()
-                .expect("failed getting cpu number")
-                .get(),
-        )
+        .run(config.workers())
         .unwrap();
 }
@@ -0,0 +1,137 @@
+use crate::models::enums::{QueryClass, QueryType, ResultCode};
+use crate::models::name::Name;
+use crate::models::rdata::RecordData;
+use crate::models::record::RawRecord;
+use crate::smart_buffer::SmartBuffer;
+use anyhow::{bail, Result};
+
+/// What a client without EDNS can receive over UDP.
+pub const MIN_UDP_PAYLOAD_SIZE: u16 = 512;
+/// Avoids IP fragmentation on virtually every path (DNS flag day 2020).
+pub const DEFAULT_UDP_PAYLOAD_SIZE: u16 = 1232;
+
+pub const EDNS_VERSION: u8 = 0;
+
+const DNSSEC_OK: u32 = 1 << 15;
+
+/// Option code of Extended DNS Errors (RFC 8914).
+const EXTENDED_ERROR: u16 = 15;
+/// INFO-CODE of answers served from expired cache data.
+pub const EDE_STALE_ANSWER: u16 = 3;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EdnsOption {
+    pub(in crate::models) code: u16,
+    pub(in crate::models) data: Vec<u8>,
+}
+
+/// The OPT pseudo-record of RFC 6891. It lives in the additional section on
+/// the wire but is kept apart from the records in `DnsPacket`.
+#[derive(Clone, Debug)]
+pub struct Edns {
+    pub(in crate::models) udp_payload_size: u16,
+    /// Upper 8 bits of the 12-bit result code.
+    pub(in crate::models) extended_rcode: u8,
+    pub(in crate::models) version: u8,
+    pub(in crate::models) dnssec_ok: bool,
+    pub(in crate::models) options: Vec<EdnsOption>,
+}
+
+impl Edns {
+    pub fn new(udp_payload_size: u16) -> Self {
+        Self {
+            udp_payload_size,
+            extended_rcode: 0,
+            version: EDNS_VERSION,
+            dnssec_ok: false,
+            options: Vec::new(),
+        }
+    }
+
+    pub fn with_dnssec_ok(mut self, dnssec_ok: bool) -> Self {
+        self.dnssec_ok = dnssec_ok;
+        self
+    }
+
+    pub fn with_extended_error(mut self, info_code: u16) -> Self {
+        self.options.push(EdnsOption {
+            code: EXTENDED_ERROR,
+            data: info_code.to_be_bytes().to_vec(),
+        });
+        self
+    }
+
+    /// Values below 512 are treated as 512, as RFC 6891 requires.
+    pub fn udp_payload_size(&self) -> u16 {
+        self.udp_payload_size.max(MIN_UDP_PAYLOAD_SIZE)
+    }
+
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    pub fn dnssec_ok(&self) -> bool {
+        self.dnssec_ok
+    }
+
+    /// Size of the OPT record on the wire.
+    pub(in crate::models) fn wire_len(&self) -> usize {
+        // root name, type, class, TTL and RDATA length
+        11 + self
+            .options
+            .iter()
+            .map(|option| 4 + option.data.len())
+            .sum::<usize>()
+    }
+
+    pub(in crate::models) fn from_record(record: RawRecord) -> Result<Self> {
+        if !record.name.is_root() {
+            bail!("OPT record owned by '{}' instead of the root", record.name);
+        }
+        let RecordData::Unknown(data) = record.data else {
+            bail!("malformed OPT record");
+        };
+
+        let mut options = Vec::new();
+        let mut smart_buf = SmartBuffer::new(&data);
+        while smart_buf.pos() < data.len() {
+            let code = smart_buf.read_u16()?;
+            let len = smart_buf.read_u16()?;
+            let data = smart_buf.read_slice(len as usize)?.to_vec();
+            options.push(EdnsOption { code, data });
+        }
+
+        Ok(Self {
+            udp_payload_size: u16::from(record.query_class),
+            extended_rcode: (record.ttl >> 24) as u8,
+            version: (record.ttl >> 16) as u8,
+            dnssec_ok: record.ttl & DNSSEC_OK != 0,
+            options,
+        })
+    }
+
+    pub(in crate::models) fn to_record(&self, result_code: ResultCode) -> Result<RawRecord> {
+        let mut data = vec![0u8; self.options.iter().map(|o| 4 + o.data.len()).sum()];
+        let mut smart_buf = SmartBuffer::new(&mut data);
+        for option in &self.options {
+            if option.data.len() > u16::MAX as usize {
+                bail!("EDNS option {} too long", option.code);
+            }
+            smart_buf.write_u16(option.code)?;
+            smart_buf.write_u16(option.data.len() as u16)?;
+            smart_buf.write_slice(&option.data)?;
+        }
+
+        let extended_rcode = (result_code as u16 >> 4) as u32;
+
+        Ok(RawRecord {
+            name: Name::root(),
+            query_type: QueryType::OPT,
+            query_class: QueryClass::from(self.udp_payload_size),
+            ttl: (extended_rcode << 24)
+                | ((self.version as u32) << 16)
+                | if self.dnssec_ok { DNSSEC_OK } else { 0 },
+            data: RecordData::Unknown(data),
+        })
+    }
+}
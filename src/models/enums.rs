@@ -1,4 +1,5 @@
 use anyhow::{anyhow, bail};
+use std::fmt::{Display, Formatter};
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum ResultCode {
@@ -8,12 +9,14 @@ pub enum ResultCode {
     NameError = 3,
     NotImplemented = 4,
     Refused = 5,
+    /// Extended RCODE, carried partly in the OPT record.
+    BadVersion = 16,
 }
 
-impl TryFrom<u8> for ResultCode {
+impl TryFrom<u16> for ResultCode {
     type Error = anyhow::Error;
 
-    fn try_from(value: u8) -> Result<Self, Self::Error> {
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
         match value {
             0 => Ok(Self::NoError),
             1 => Ok(Self::FormatError),
@@ -21,6 +24,7 @@ impl TryFrom<u8> for ResultCode {
             3 => Ok(Self::NameError),
             4 => Ok(Self::NotImplemented),
             5 => Ok(Self::Refused),
+            16 => Ok(Self::BadVersion),
             _ => Err(anyhow!("unsupported result code")),
         }
     }
@@ -64,9 +68,20 @@ impl From<OpCode> for u8 {
 }
 
 #[repr(u16)]
-#[derive(Copy, Clone, Debug)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
 pub enum QueryType {
     A = 1,
+    NS = 2,
+    CNAME = 5,
+    SOA = 6,
+    PTR = 12,
+    MX = 15,
+    TXT = 16,
+    AAAA = 28,
+    SRV = 33,
+    OPT = 41,
+    CAA = 257,
     Unknown(u16),
 }
 
@@ -74,6 +89,16 @@ impl From<u16> for QueryType {
     fn from(value: u16) -> Self {
         match value {
             1 => Self::A,
+            2 => Self::NS,
+            5 => Self::CNAME,
+            6 => Self::SOA,
+            12 => Self::PTR,
+            15 => Self::MX,
+            16 => Self::TXT,
+            28 => Self::AAAA,
+            33 => Self::SRV,
+            41 => Self::OPT,
+            257 => Self::CAA,
             value => Self::Unknown(value),
         }
     }
@@ -83,9 +108,22 @@ impl TryFrom<&str> for QueryType {
     type Error = anyhow::Error;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        match value {
+        match value.to_ascii_uppercase().as_str() {
             "A" => Ok(Self::A),
-            _ => bail!("unknown query type"),
+            "NS" => Ok(Self::NS),
+            "CNAME" => Ok(Self::CNAME),
+            "SOA" => Ok(Self::SOA),
+            "PTR" => Ok(Self::PTR),
+            "MX" => Ok(Self::MX),
+            "TXT" => Ok(Self::TXT),
+            "AAAA" => Ok(Self::AAAA),
+            "SRV" => Ok(Self::SRV),
+            "OPT" => Ok(Self::OPT),
+            "CAA" => Ok(Self::CAA),
+            value => match value.strip_prefix("TYPE").map(str::parse::<u16>) {
+                Some(Ok(value)) => Ok(Self::from(value)),
+                _ => bail!("unknown query type"),
+            },
         }
     }
 }
@@ -94,15 +132,36 @@ impl From<QueryType> for u16 {
     fn from(value: QueryType) -> Self {
         match value {
             QueryType::A => 1,
+            QueryType::NS => 2,
+            QueryType::CNAME => 5,
+            QueryType::SOA => 6,
+            QueryType::PTR => 12,
+            QueryType::MX => 15,
+            QueryType::TXT => 16,
+            QueryType::AAAA => 28,
+            QueryType::SRV => 33,
+            QueryType::OPT => 41,
+            QueryType::CAA => 257,
             QueryType::Unknown(value) => value,
         }
     }
 }
 
+impl Display for QueryType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QueryType::Unknown(value) => write!(f, "TYPE{value}"),
+            known => write!(f, "{known:?}"),
+        }
+    }
+}
+
 #[repr(u16)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
 pub enum QueryClass {
     IN = 1,
+    CH = 3,
+    HS = 4,
     Unknown(u16),
 }
 
@@ -110,9 +169,14 @@ impl TryFrom<&str> for QueryClass {
     type Error = anyhow::Error;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        match value {
+        match value.to_ascii_uppercase().as_str() {
             "IN" => Ok(Self::IN),
-            _ => bail!("unknown query class"),
+            "CH" => Ok(Self::CH),
+            "HS" => Ok(Self::HS),
+            value => match value.strip_prefix("CLASS").map(str::parse::<u16>) {
+                Some(Ok(value)) => Ok(Self::from(value)),
+                _ => bail!("unknown query class"),
+            },
         }
     }
 }
@@ -121,6 +185,8 @@ impl From<QueryClass> for u16 {
     fn from(value: QueryClass) -> Self {
         match value {
             QueryClass::IN => 1,
+            QueryClass::CH => 3,
+            QueryClass::HS => 4,
             QueryClass::Unknown(value) => value,
         }
     }
@@ -130,7 +196,18 @@ impl From<u16> for QueryClass {
     fn from(value: u16) -> Self {
         match value {
             1 => Self::IN,
+            3 => Self::CH,
+            4 => Self::HS,
             value => Self::Unknown(value),
         }
     }
 }
+
+impl Display for QueryClass {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QueryClass::Unknown(value) => write!(f, "CLASS{value}"),
+            known => write!(f, "{known:?}"),
+        }
+    }
+}
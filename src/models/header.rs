@@ -2,7 +2,7 @@ use crate::models::enums::{MessageType, OpCode, ResultCode};
 use crate::smart_buffer::SmartBuffer;
 use anyhow::Result;
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Header {
     pub(in crate::models) id: u16,
     pub(in crate::models) message_type: MessageType,
@@ -36,7 +36,8 @@ impl Header {
         let truncation = (flags & (1 << 9)) > 0;
         let recursion_desired = (flags & (1 << 8)) > 0;
         let recursion_available = (flags & (1 << 7)) > 0;
-        let result_code = ResultCode::try_from((flags & 0x0F) as u8)?;
+        // a code this server doesn't know still tells the request failed
+        let result_code = ResultCode::try_from(flags & 0x0F).unwrap_or(ResultCode::ServerFailure);
 
         // z == flags & (0x7 << 4)
 
@@ -75,7 +76,10 @@ impl Header {
                 | ((self.message_type as u8) << 7),
         )?;
 
-        smart_buf.write_u8((self.result_code as u8) | ((self.recursion_available as u8) << 7))?;
+        // the upper bits of extended result codes go into the OPT record
+        smart_buf.write_u8(
+            ((self.result_code as u16 & 0x0F) as u8) | ((self.recursion_available as u8) << 7),
+        )?;
 
         smart_buf.write_u16(self.question_entities_count)?;
         smart_buf.write_u16(self.answer_entities_count)?;
@@ -1,15 +1,29 @@
+mod edns;
 mod enums;
 mod header;
+mod name;
 mod packet;
 mod packet_builder;
 mod question;
+mod rdata;
 mod record;
+mod text;
 
+pub use edns::{
+    Edns, DEFAULT_UDP_PAYLOAD_SIZE, EDE_STALE_ANSWER, EDNS_VERSION, MIN_UDP_PAYLOAD_SIZE,
+};
 pub use enums::*;
+pub use name::Name;
 pub use packet::*;
 pub use packet_builder::{DnsPacketBuilder, RawRecordType};
 pub use question::Question;
+pub use rdata::RecordData;
+pub use record::RawRecord;
+pub use text::parse_ttl;
 
-pub fn new_packet_buffer() -> Vec<u8> {
-    vec![0u8; 512]
+/// Most CNAMEs followed for one question, longer chains are an error.
+pub const MAX_CNAME_CHAIN_LENGTH: usize = 8;
+
+pub fn new_packet_buffer(size: u16) -> Vec<u8> {
+    vec![0u8; size as usize]
 }
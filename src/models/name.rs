@@ -0,0 +1,274 @@
+use anyhow::{anyhow, bail, Context, Result};
+use std::cmp::Ordering;
+use std::fmt::{Debug, Display, Formatter};
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+pub const MAX_LABEL_LENGTH: usize = 63;
+/// Wire length including the root label.
+pub const MAX_NAME_LENGTH: usize = 255;
+
+/// A domain name kept as raw labels, so binary labels and the original case
+/// survive a round trip. Comparisons ignore ASCII case as RFC 4343 requires.
+#[derive(Clone, Default)]
+pub struct Name {
+    /// Length-prefixed labels as on the wire, without the root label.
+    wire: Vec<u8>,
+}
+
+impl Name {
+    pub fn root() -> Self {
+        Self::default()
+    }
+
+    pub fn from_labels<'a, I: IntoIterator<Item = &'a [u8]>>(labels: I) -> Result<Self> {
+        let mut wire = Vec::new();
+        for label in labels {
+            if label.is_empty() {
+                bail!("empty label");
+            }
+            if label.len() > MAX_LABEL_LENGTH {
+                bail!("label longer than {MAX_LABEL_LENGTH} bytes");
+            }
+            wire.push(label.len() as u8);
+            wire.extend_from_slice(label);
+        }
+
+        Self::from_wire(wire)
+    }
+
+    fn from_wire(wire: Vec<u8>) -> Result<Self> {
+        if wire.len() + 1 > MAX_NAME_LENGTH {
+            bail!("name longer than {MAX_NAME_LENGTH} bytes");
+        }
+
+        Ok(Self { wire })
+    }
+
+    /// Parses a name in presentation format, resolving `\X` and `\DDD`
+    /// escapes. `@` stands for the origin and names without a trailing dot are
+    /// relative to it.
+    pub fn parse(text: &str, origin: &Name) -> Result<Self> {
+        match text {
+            "@" => return Ok(origin.clone()),
+            "." => return Ok(Self::root()),
+            _ => {}
+        }
+
+        let mut labels = Vec::new();
+        let mut label = Vec::new();
+        let mut absolute = false;
+
+        let mut bytes = text.bytes().peekable();
+        while let Some(byte) = bytes.next() {
+            match byte {
+                b'.' => {
+                    if label.is_empty() {
+                        bail!("empty label in name '{text}'");
+                    }
+                    labels.push(std::mem::take(&mut label));
+                    absolute = bytes.peek().is_none();
+                }
+                b'\\' => {
+                    let escaped = bytes
+                        .next()
+                        .ok_or_else(|| anyhow!("dangling escape in name '{text}'"))?;
+                    if escaped.is_ascii_digit() {
+                        let digits = [
+                            escaped,
+                            bytes.next().unwrap_or(0),
+                            bytes.next().unwrap_or(0),
+                        ];
+                        let code = std::str::from_utf8(&digits)
+                            .ok()
+                            .and_then(|digits| digits.parse::<u8>().ok())
+                            .ok_or_else(|| anyhow!("invalid \\DDD escape in name '{text}'"))?;
+                        label.push(code);
+                    } else {
+                        label.push(escaped);
+                    }
+                }
+                byte => label.push(byte),
+            }
+        }
+        if !label.is_empty() {
+            labels.push(label);
+        }
+
+        let name = Self::from_labels(labels.iter().map(Vec::as_slice))
+            .with_context(|| format!("invalid name '{text}'"))?;
+        if absolute {
+            Ok(name)
+        } else {
+            name.concat(origin)
+                .with_context(|| format!("invalid name '{text}'"))
+        }
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.wire.is_empty()
+    }
+
+    /// The labels from the leftmost one to the one below the root.
+    pub fn labels(&self) -> Labels<'_> {
+        Labels { wire: &self.wire }
+    }
+
+    /// Length on the wire, root label included.
+    pub fn wire_len(&self) -> usize {
+        self.wire.len() + 1
+    }
+
+    pub fn label_count(&self) -> usize {
+        self.labels().count()
+    }
+
+    pub fn first_label(&self) -> Option<&[u8]> {
+        self.labels().next()
+    }
+
+    /// The name without its leftmost label, `None` for the root.
+    pub fn parent(&self) -> Option<Name> {
+        let len = *self.wire.first()? as usize;
+        Some(Self {
+            wire: self.wire[1 + len..].to_vec(),
+        })
+    }
+
+    pub fn concat(&self, suffix: &Name) -> Result<Name> {
+        Self::from_wire([self.wire.as_slice(), suffix.wire.as_slice()].concat())
+    }
+
+    /// The name itself followed by all its parents, ending with the root.
+    pub fn ancestors(&self) -> impl Iterator<Item = Name> {
+        std::iter::successors(Some(self.clone()), Name::parent)
+    }
+
+    /// The rightmost `label_count` labels, or the whole name if it has fewer.
+    pub fn suffix(&self, label_count: usize) -> Name {
+        let skip = self.label_count().saturating_sub(label_count);
+        self.ancestors().nth(skip).unwrap_or_default()
+    }
+
+    /// Whether the name equals `other` or lies below it.
+    pub fn is_subdomain_of(&self, other: &Name) -> bool {
+        let count = other.label_count();
+        self.label_count() >= count && self.suffix(count) == *other
+    }
+}
+
+pub struct Labels<'a> {
+    wire: &'a [u8],
+}
+
+impl<'a> Iterator for Labels<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (&len, rest) = self.wire.split_first()?;
+        let (label, rest) = rest.split_at(len as usize);
+        self.wire = rest;
+        Some(label)
+    }
+}
+
+/// Length bytes are at most 63, below any ASCII letter, so the whole wire
+/// form can be compared and hashed with the case folded.
+impl PartialEq for Name {
+    fn eq(&self, other: &Self) -> bool {
+        self.wire.eq_ignore_ascii_case(&other.wire)
+    }
+}
+
+impl Eq for Name {}
+
+impl Hash for Name {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_usize(self.wire.len());
+        for byte in &self.wire {
+            state.write_u8(byte.to_ascii_lowercase());
+        }
+    }
+}
+
+/// The canonical order of RFC 4034 6.1: labels compared right to left as
+/// lowercased byte strings, a name sorting before its subdomains.
+impl Ord for Name {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let left = self.labels().collect::<Vec<_>>();
+        let right = other.labels().collect::<Vec<_>>();
+
+        for (a, b) in left.iter().rev().zip(right.iter().rev()) {
+            let ordering = a
+                .iter()
+                .map(u8::to_ascii_lowercase)
+                .cmp(b.iter().map(u8::to_ascii_lowercase));
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+
+        left.len().cmp(&right.len())
+    }
+}
+
+impl PartialOrd for Name {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Presentation format, always absolute.
+impl Display for Name {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.is_root() {
+            return write!(f, ".");
+        }
+
+        for label in self.labels() {
+            for &byte in label {
+                match byte {
+                    b'.' | b'\\' | b'"' | b'(' | b')' | b';' | b'@' | b'$' => {
+                        write!(f, "\\{}", byte as char)?
+                    }
+                    0x21..=0x7E => write!(f, "{}", byte as char)?,
+                    _ => write!(f, "\\{byte:03}")?,
+                }
+            }
+            write!(f, ".")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Debug for Name {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "\"{self}\"")
+    }
+}
+
+/// Parses an absolute name; a trailing dot is optional.
+impl FromStr for Name {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Self> {
+        Self::parse(text, &Self::root())
+    }
+}
+
+impl TryFrom<&str> for Name {
+    type Error = anyhow::Error;
+
+    fn try_from(text: &str) -> Result<Self> {
+        text.parse()
+    }
+}
+
+impl TryFrom<&String> for Name {
+    type Error = anyhow::Error;
+
+    fn try_from(text: &String) -> Result<Self> {
+        text.parse()
+    }
+}
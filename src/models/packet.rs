@@ -1,9 +1,10 @@
+use crate::models::edns::Edns;
 use crate::models::header::Header;
 use crate::models::question::Question;
 use crate::models::record::RawRecord;
-use crate::models::ResultCode;
+use crate::models::{MessageType, QueryType, ResultCode};
 use crate::smart_buffer::SmartBuffer;
-use anyhow::Result;
+use anyhow::{bail, Result};
 
 #[derive(Debug)]
 pub struct DnsPacket {
@@ -15,6 +16,10 @@ pub struct DnsPacket {
 pub struct DnsPacketMeta {
     pub(in crate::models) header: Header,
     pub(in crate::models) questions: Vec<Question>,
+    pub(in crate::models) edns: Option<Edns>,
+    /// Why an OPT record was left out, the message deserves a FORMERR
+    /// (RFC 6891 6.1.1).
+    pub(in crate::models) opt_error: Option<String>,
 }
 
 #[derive(Clone, Debug)]
@@ -29,7 +34,7 @@ impl DnsPacket {
         let buf = &buf.as_ref();
         let mut smart_buf = SmartBuffer::new(buf);
 
-        let header = Header::from_bytes(&mut smart_buf)?;
+        let mut header = Header::from_bytes(&mut smart_buf)?;
 
         let mut questions = Vec::with_capacity(header.question_entities_count() as usize);
         for _ in 0..header.question_entities_count() {
@@ -37,26 +42,55 @@ impl DnsPacket {
             questions.push(question);
         }
 
-        let mut answers = Vec::with_capacity(header.answer_entities_count() as usize);
-        for _ in 0..header.answer_entities_count() {
-            let record = RawRecord::from_bytes(&mut smart_buf)?;
-            answers.push(record);
-        }
+        let mut opt_error = None;
 
+        let mut answers = Vec::with_capacity(header.answer_entities_count() as usize);
         let mut authorities = Vec::with_capacity(header.authority_entities_count() as usize);
-        for _ in 0..header.authority_entities_count() {
-            let record = RawRecord::from_bytes(&mut smart_buf)?;
-            authorities.push(record);
+        for (section, count) in [
+            (&mut answers, header.answer_entities_count()),
+            (&mut authorities, header.authority_entities_count()),
+        ] {
+            for _ in 0..count {
+                let record = RawRecord::from_bytes(&mut smart_buf)?;
+                if record.query_type == QueryType::OPT {
+                    opt_error = Some("OPT record outside of the additional section".to_string());
+                } else {
+                    section.push(record);
+                }
+            }
         }
 
         let mut additional = Vec::with_capacity(header.additional_entities_count() as usize);
+        let mut edns: Option<Edns> = None;
         for _ in 0..header.additional_entities_count() {
             let record = RawRecord::from_bytes(&mut smart_buf)?;
-            additional.push(record);
+            if record.query_type != QueryType::OPT {
+                additional.push(record);
+            } else if edns.is_some() {
+                opt_error = Some("more than one OPT record".to_string());
+            } else {
+                match Edns::from_record(record) {
+                    Ok(record) => edns = Some(record),
+                    Err(e) => opt_error = Some(e.to_string()),
+                }
+            }
+        }
+
+        if let Some(edns) = edns.as_ref().filter(|edns| edns.extended_rcode != 0) {
+            // like unknown codes in the header
+            header.result_code = ResultCode::try_from(
+                ((edns.extended_rcode as u16) << 4) | header.result_code as u16,
+            )
+            .unwrap_or(ResultCode::ServerFailure);
         }
 
         Ok(Self {
-            meta: DnsPacketMeta { header, questions },
+            meta: DnsPacketMeta {
+                header,
+                questions,
+                edns,
+                opt_error,
+            },
             base: DnsPacketBase {
                 answers,
                 authorities,
@@ -65,56 +99,143 @@ impl DnsPacket {
         })
     }
 
-    pub fn to_bytes<B: AsMut<[u8]> + AsRef<[u8]>>(&self, buf: B) -> Result<()> {
+    /// Returns the length of the written message.
+    ///
+    /// The message is cut down to fit the buffer: whole RRsets are dropped
+    /// from the end and the TC bit is set unless only additional records went
+    /// missing. The OPT record always stays.
+    pub fn to_bytes<B: AsMut<[u8]> + AsRef<[u8]>>(&self, buf: B) -> Result<usize> {
         let mut smart_buf = SmartBuffer::new(buf);
 
-        self.meta.header.to_bytes(&mut smart_buf)?;
+        let mut header = self.meta.header.clone();
+        let opt = match &self.meta.edns {
+            Some(edns) => Some(edns.to_record(header.result_code)?),
+            None if header.result_code as u16 > 0x0F => {
+                bail!("result code {:?} needs an OPT record", header.result_code)
+            }
+            None => None,
+        };
+
+        // rewritten with the actual counts once the records are in
+        header.to_bytes(&mut smart_buf)?;
 
         for question in &self.meta.questions {
             question.to_bytes(&mut smart_buf)?;
         }
 
-        for answer in &self.base.answers {
-            answer.to_bytes(&mut smart_buf)?;
-        }
+        let limit = smart_buf
+            .capacity()
+            .saturating_sub(self.meta.edns.as_ref().map_or(0, Edns::wire_len));
+
+        let (answers, answers_complete) =
+            Self::write_rrsets(&mut smart_buf, &self.base.answers, limit)?;
+        let (authorities, authorities_complete) = if answers_complete {
+            Self::write_rrsets(&mut smart_buf, &self.base.authorities, limit)?
+        } else {
+            (0, false)
+        };
+        let (additional, additional_complete) = if authorities_complete {
+            Self::write_rrsets(&mut smart_buf, &self.base.additional, limit)?
+        } else {
+            (0, false)
+        };
 
-        for authority in &self.base.authorities {
-            authority.to_bytes(&mut smart_buf)?;
+        if let Some(opt) = &opt {
+            opt.to_bytes(&mut smart_buf)?;
         }
+        let len = smart_buf.pos();
+
+        // a referral is of no use without its glue (RFC 9471)
+        header.truncation |= !authorities_complete || (!additional_complete && self.is_referral());
+        header.answer_entities_count = answers as u16;
+        header.authority_entities_count = authorities as u16;
+        header.additional_entities_count = (additional + opt.is_some() as usize) as u16;
 
-        for additional in &self.base.additional {
-            additional.to_bytes(&mut smart_buf)?;
+        smart_buf.seek(0)?;
+        header.to_bytes(&mut smart_buf)?;
+
+        Ok(len)
+    }
+
+    /// Writes the records as long as whole RRsets fit below `limit`. Returns
+    /// how many were written and whether that's all of them.
+    fn write_rrsets<T: AsMut<[u8]> + AsRef<[u8]>>(
+        smart_buf: &mut SmartBuffer<T>,
+        records: &[RawRecord],
+        limit: usize,
+    ) -> Result<(usize, bool)> {
+        let mut written = 0;
+
+        for rrset in records.chunk_by(|a, b| a.is_same_rrset(b)) {
+            let start = smart_buf.pos();
+
+            let fits = rrset
+                .iter()
+                .try_for_each(|record| record.to_bytes(smart_buf))
+                .is_ok()
+                && smart_buf.pos() <= limit;
+            if !fits {
+                smart_buf.truncate(start)?;
+                return Ok((written, false));
+            }
+
+            written += rrset.len();
         }
 
-        Ok(())
+        Ok((written, true))
+    }
+
+    /// A non-authoritative answer pointing at the name servers of a delegated
+    /// zone, whose additional records are their glue.
+    fn is_referral(&self) -> bool {
+        !self.meta.header.authoritative_answer
+            && self.base.answers.is_empty()
+            && self
+                .base
+                .authorities
+                .iter()
+                .any(|record| record.query_type == QueryType::NS)
     }
 
     pub fn id(&self) -> u16 {
         self.meta.header.id
     }
 
+    pub fn is_response(&self) -> bool {
+        matches!(self.meta.header.message_type, MessageType::Response)
+    }
+
     pub fn questions(&self) -> &[Question] {
         self.meta.questions.as_slice()
     }
 
+    pub fn is_truncated(&self) -> bool {
+        self.meta.header.truncation
+    }
+
     pub fn recursion_desired(&self) -> bool {
         self.meta.header.recursion_desired
     }
 
-    pub fn min_ttl(&self) -> Option<u32> {
-        let ttl1 = self.base.answers.iter().map(|a| a.ttl).min();
-        let ttl2 = self.base.authorities.iter().map(|a| a.ttl).min();
-        let ttl3 = self.base.additional.iter().map(|a| a.ttl).min();
+    pub fn edns(&self) -> Option<&Edns> {
+        self.meta.edns.as_ref()
+    }
 
-        if let Some(default) = ttl1.or(ttl2).or(ttl3) {
-            let ttl1 = ttl1.unwrap_or(default);
-            let ttl2 = ttl2.unwrap_or(default);
-            let ttl3 = ttl3.unwrap_or(default);
+    /// Set when an OPT record was misplaced, duplicated or malformed.
+    pub fn opt_error(&self) -> Option<&str> {
+        self.meta.opt_error.as_deref()
+    }
 
-            Some(ttl1.min(ttl2).min(ttl3))
-        } else {
-            None
-        }
+    pub fn answers(&self) -> &[RawRecord] {
+        self.base.answers.as_slice()
+    }
+
+    pub fn authorities(&self) -> &[RawRecord] {
+        self.base.authorities.as_slice()
+    }
+
+    pub fn additional(&self) -> &[RawRecord] {
+        self.base.additional.as_slice()
     }
 
     pub fn base(&self) -> &DnsPacketBase {
@@ -1,7 +1,10 @@
+use crate::models::edns::Edns;
 use crate::models::enums::{MessageType, OpCode, QueryClass, QueryType, ResultCode};
 use crate::models::header::Header;
+use crate::models::name::Name;
 use crate::models::packet::{DnsPacketBase, DnsPacketMeta};
 use crate::models::question::Question;
+use crate::models::rdata::RecordData;
 use crate::models::record::RawRecord;
 use crate::models::DnsPacket;
 use anyhow::{anyhow, Result};
@@ -10,6 +13,7 @@ use rand::random;
 #[derive(Default)]
 pub struct DnsPacketBuilder {
     id: Option<u16>,
+    authoritative_answer: bool,
     recursion_desired: bool,
     recursion_available: bool,
     result_code: Option<ResultCode>,
@@ -19,24 +23,29 @@ pub struct DnsPacketBuilder {
     answers: Vec<RawRecord>,
     authorities: Vec<RawRecord>,
     additional: Vec<RawRecord>,
+    edns: Option<Edns>,
 }
 
-pub struct RawRecordBuilder<S: Into<String>> {
+pub struct RawRecordBuilder<S: TryInto<Name>> {
     packet_builder: DnsPacketBuilder,
     name: Option<S>,
     query_type: Option<QueryType>,
     query_class: Option<QueryClass>,
     ttl: Option<u32>,
-    rdata: Option<Vec<u8>>,
+    data: Option<RecordData>,
 }
 
+#[derive(Clone, Copy)]
 pub enum RawRecordType {
     Answer,
-    _Authority,
-    _Additional,
+    Authority,
+    Additional,
 }
 
-impl<S: Into<String>> RawRecordBuilder<S> {
+impl<S: TryInto<Name>> RawRecordBuilder<S>
+where
+    anyhow::Error: From<S::Error>,
+{
     pub fn name(mut self, name: S) -> Self {
         self.name = Some(name);
         self
@@ -57,30 +66,32 @@ impl<S: Into<String>> RawRecordBuilder<S> {
         self
     }
 
-    pub fn rdata(mut self, rdata: Vec<u8>) -> Self {
-        self.rdata = Some(rdata);
+    pub fn data(mut self, data: RecordData) -> Self {
+        self.data = Some(data);
         self
     }
 
     pub fn add_raw_record(mut self, record_type: RawRecordType) -> Result<DnsPacketBuilder> {
-        let rdata = self.rdata.unwrap_or_default();
+        let data = self.data.ok_or(anyhow!("record data can't be empty"))?;
 
         let raw_record = RawRecord {
             name: self
                 .name
                 .ok_or(anyhow!("record name can't be empty"))?
-                .into(),
-            query_type: self.query_type.unwrap_or(QueryType::A),
+                .try_into()?,
+            query_type: self
+                .query_type
+                .or(data.query_type())
+                .ok_or(anyhow!("record type can't be empty"))?,
             query_class: self.query_class.unwrap_or(QueryClass::IN),
             ttl: self.ttl.unwrap_or(15),
-            rdata_length: rdata.len() as u16,
-            rdata,
+            data,
         };
 
         match record_type {
             RawRecordType::Answer => self.packet_builder.answers.push(raw_record),
-            RawRecordType::_Authority => self.packet_builder.authorities.push(raw_record),
-            RawRecordType::_Additional => self.packet_builder.additional.push(raw_record),
+            RawRecordType::Authority => self.packet_builder.authorities.push(raw_record),
+            RawRecordType::Additional => self.packet_builder.additional.push(raw_record),
         }
 
         Ok(self.packet_builder)
@@ -90,7 +101,7 @@ impl<S: Into<String>> RawRecordBuilder<S> {
 // TODO: remove
 #[allow(dead_code)]
 #[derive(Default)]
-pub struct QuestionBuilder<S: Into<String>> {
+pub struct QuestionBuilder<S: TryInto<Name>> {
     packet_builder: DnsPacketBuilder,
     q_type: Option<QueryType>,
     q_class: Option<QueryClass>,
@@ -99,7 +110,10 @@ pub struct QuestionBuilder<S: Into<String>> {
 
 // TODO: remove
 #[allow(dead_code)]
-impl<S: Into<String>> QuestionBuilder<S> {
+impl<S: TryInto<Name>> QuestionBuilder<S>
+where
+    anyhow::Error: From<S::Error>,
+{
     pub fn q_type(mut self, q_type: QueryType) -> Self {
         self.q_type = Some(q_type);
         self
@@ -117,7 +131,7 @@ impl<S: Into<String>> QuestionBuilder<S> {
 
     pub fn add_question(mut self) -> Result<DnsPacketBuilder> {
         self.packet_builder.questions.push(Question {
-            q_name: self.q_name.ok_or(anyhow!("empty q_name"))?.into(),
+            q_name: self.q_name.ok_or(anyhow!("empty q_name"))?.try_into()?,
             q_type: self.q_type.unwrap_or(QueryType::A),
             q_class: self.q_class.unwrap_or(QueryClass::IN),
         });
@@ -127,6 +141,11 @@ impl<S: Into<String>> QuestionBuilder<S> {
 }
 
 impl DnsPacketBuilder {
+    pub fn authoritative_answer(mut self, authoritative_answer: bool) -> Self {
+        self.authoritative_answer = authoritative_answer;
+        self
+    }
+
     pub fn recursion_desired(mut self, recursion_desired: bool) -> Self {
         self.recursion_desired = recursion_desired;
         self
@@ -152,7 +171,7 @@ impl DnsPacketBuilder {
         self
     }
 
-    pub fn _new_question<S: Into<String>>(self) -> QuestionBuilder<S> {
+    pub fn _new_question<S: TryInto<Name>>(self) -> QuestionBuilder<S> {
         QuestionBuilder {
             packet_builder: self,
             q_type: None,
@@ -161,14 +180,14 @@ impl DnsPacketBuilder {
         }
     }
 
-    pub fn new_raw_record<S: Into<String>>(self) -> RawRecordBuilder<S> {
+    pub fn new_raw_record<S: TryInto<Name>>(self) -> RawRecordBuilder<S> {
         RawRecordBuilder {
             packet_builder: self,
             name: None,
             query_type: None,
             query_class: None,
             ttl: None,
-            rdata: None,
+            data: None,
         }
     }
 
@@ -177,6 +196,21 @@ impl DnsPacketBuilder {
         self
     }
 
+    pub fn with_answers<I: IntoIterator<Item = RawRecord>>(mut self, answers: I) -> Self {
+        self.answers.extend(answers);
+        self
+    }
+
+    pub fn with_authorities<I: IntoIterator<Item = RawRecord>>(mut self, authorities: I) -> Self {
+        self.authorities.extend(authorities);
+        self
+    }
+
+    pub fn edns(mut self, edns: Edns) -> Self {
+        self.edns = Some(edns);
+        self
+    }
+
     pub fn with_base(mut self, base: DnsPacketBase) -> Self {
         self.base = Some(base);
         self
@@ -195,7 +229,7 @@ impl DnsPacketBuilder {
                     id: if let Some(id) = self.id { id } else { random() },
                     message_type: self.message_type.unwrap_or(MessageType::Query),
                     opcode: OpCode::Query,
-                    authoritative_answer: false,
+                    authoritative_answer: self.authoritative_answer,
                     truncation: false,
                     recursion_desired: self.recursion_desired,
                     recursion_available: self.recursion_available,
@@ -203,9 +237,13 @@ impl DnsPacketBuilder {
                     question_entities_count: self.questions.len() as u16,
                     answer_entities_count: base.answers.len() as u16,
                     authority_entities_count: base.authorities.len() as u16,
-                    additional_entities_count: base.additional.len() as u16,
+                    additional_entities_count: (base.additional.len()
+                        + self.edns.is_some() as usize)
+                        as u16,
                 },
                 questions: self.questions,
+                edns: self.edns,
+                opt_error: None,
             },
             base,
         }
@@ -1,15 +1,24 @@
 use crate::models::enums::{QueryClass, QueryType};
+use crate::models::name::Name;
 use crate::smart_buffer::SmartBuffer;
 use anyhow::Result;
 
 #[derive(Clone, Debug)]
 pub struct Question {
-    pub(in crate::models) q_name: String,
+    pub(in crate::models) q_name: Name,
     pub(in crate::models) q_type: QueryType,
     pub(in crate::models) q_class: QueryClass,
 }
 
 impl Question {
+    pub fn new(name: Name, query_type: QueryType, query_class: QueryClass) -> Self {
+        Self {
+            q_name: name,
+            q_type: query_type,
+            q_class: query_class,
+        }
+    }
+
     pub(in crate::models) fn from_bytes<T: AsRef<[u8]>>(
         smart_buf: &mut SmartBuffer<T>,
     ) -> Result<Self> {
@@ -34,7 +43,20 @@ impl Question {
         Ok(())
     }
 
-    pub fn name(&self) -> &String {
+    pub fn name(&self) -> &Name {
         &self.q_name
     }
+
+    /// Whether both questions ask the same, ignoring the case of the name.
+    pub fn matches(&self, other: &Question) -> bool {
+        self.q_name == other.q_name && self.q_type == other.q_type && self.q_class == other.q_class
+    }
+
+    pub fn query_type(&self) -> QueryType {
+        self.q_type
+    }
+
+    pub fn query_class(&self) -> QueryClass {
+        self.q_class
+    }
 }
@@ -0,0 +1,356 @@
+use crate::models::enums::QueryType;
+use crate::models::name::Name;
+use crate::models::text::{format_character_string, parse_character_string, parse_ttl};
+use crate::smart_buffer::SmartBuffer;
+use anyhow::{anyhow, bail, Context, Result};
+use std::fmt::{Display, Formatter};
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+const GENERIC_RDATA_MARKER: &str = "\\#";
+
+/// Typed RDATA of a resource record.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RecordData {
+    A(Ipv4Addr),
+    AAAA(Ipv6Addr),
+    NS(Name),
+    CNAME(Name),
+    PTR(Name),
+    MX {
+        preference: u16,
+        exchange: Name,
+    },
+    SOA {
+        mname: Name,
+        rname: Name,
+        serial: u32,
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+        minimum: u32,
+    },
+    TXT(Vec<Vec<u8>>),
+    SRV {
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: Name,
+    },
+    CAA {
+        flags: u8,
+        tag: String,
+        value: Vec<u8>,
+    },
+    /// RDATA of a type we don't know, kept as is (RFC 3597).
+    Unknown(Vec<u8>),
+}
+
+impl RecordData {
+    /// Type of the data, `None` for data of unknown types.
+    pub fn query_type(&self) -> Option<QueryType> {
+        Some(match self {
+            RecordData::A(_) => QueryType::A,
+            RecordData::AAAA(_) => QueryType::AAAA,
+            RecordData::NS(_) => QueryType::NS,
+            RecordData::CNAME(_) => QueryType::CNAME,
+            RecordData::PTR(_) => QueryType::PTR,
+            RecordData::MX { .. } => QueryType::MX,
+            RecordData::SOA { .. } => QueryType::SOA,
+            RecordData::TXT(_) => QueryType::TXT,
+            RecordData::SRV { .. } => QueryType::SRV,
+            RecordData::CAA { .. } => QueryType::CAA,
+            RecordData::Unknown(_) => return None,
+        })
+    }
+
+    pub(in crate::models) fn from_bytes<T: AsRef<[u8]>>(
+        smart_buf: &mut SmartBuffer<T>,
+        query_type: QueryType,
+        rdata_length: u16,
+    ) -> Result<Self> {
+        let start = smart_buf.pos();
+        let end = start + rdata_length as usize;
+
+        let data = match query_type {
+            QueryType::A => {
+                let octets: [u8; 4] = smart_buf
+                    .read_slice(rdata_length as usize)?
+                    .try_into()
+                    .map_err(|_| anyhow!("invalid A record length {rdata_length}"))?;
+                RecordData::A(Ipv4Addr::from(octets))
+            }
+            QueryType::AAAA => {
+                let octets: [u8; 16] = smart_buf
+                    .read_slice(rdata_length as usize)?
+                    .try_into()
+                    .map_err(|_| anyhow!("invalid AAAA record length {rdata_length}"))?;
+                RecordData::AAAA(Ipv6Addr::from(octets))
+            }
+            QueryType::NS => RecordData::NS(smart_buf.read_qname()?),
+            QueryType::CNAME => RecordData::CNAME(smart_buf.read_qname()?),
+            QueryType::PTR => RecordData::PTR(smart_buf.read_qname()?),
+            QueryType::MX => RecordData::MX {
+                preference: smart_buf.read_u16()?,
+                exchange: smart_buf.read_qname()?,
+            },
+            QueryType::SOA => RecordData::SOA {
+                mname: smart_buf.read_qname()?,
+                rname: smart_buf.read_qname()?,
+                serial: smart_buf.read_u32()?,
+                refresh: smart_buf.read_u32()?,
+                retry: smart_buf.read_u32()?,
+                expire: smart_buf.read_u32()?,
+                minimum: smart_buf.read_u32()?,
+            },
+            QueryType::TXT => {
+                let mut strings = Vec::new();
+                while smart_buf.pos() < end {
+                    let len = smart_buf.read_u8()?;
+                    strings.push(smart_buf.read_slice(len as usize)?.to_vec());
+                }
+                RecordData::TXT(strings)
+            }
+            QueryType::SRV => RecordData::SRV {
+                priority: smart_buf.read_u16()?,
+                weight: smart_buf.read_u16()?,
+                port: smart_buf.read_u16()?,
+                target: smart_buf.read_qname()?,
+            },
+            QueryType::CAA => {
+                let flags = smart_buf.read_u8()?;
+                let tag_length = smart_buf.read_u8()?;
+                let tag = String::from_utf8(smart_buf.read_slice(tag_length as usize)?.to_vec())
+                    .context("invalid CAA tag")?;
+                let value_length = end
+                    .checked_sub(smart_buf.pos())
+                    .ok_or_else(|| anyhow!("invalid CAA record length {rdata_length}"))?;
+                let value = smart_buf.read_slice(value_length)?.to_vec();
+                RecordData::CAA { flags, tag, value }
+            }
+            QueryType::OPT | QueryType::Unknown(_) => {
+                RecordData::Unknown(smart_buf.read_slice(rdata_length as usize)?.to_vec())
+            }
+        };
+
+        if smart_buf.pos() != end {
+            bail!("{query_type} record data doesn't match its length {rdata_length}");
+        }
+
+        Ok(data)
+    }
+
+    pub(in crate::models) fn to_bytes<T: AsMut<[u8]> + AsRef<[u8]>>(
+        &self,
+        smart_buf: &mut SmartBuffer<T>,
+    ) -> Result<()> {
+        match self {
+            RecordData::A(address) => smart_buf.write_slice(address.octets())?,
+            RecordData::AAAA(address) => smart_buf.write_slice(address.octets())?,
+            RecordData::NS(name) | RecordData::CNAME(name) | RecordData::PTR(name) => {
+                smart_buf.write_qname(name)?
+            }
+            RecordData::MX {
+                preference,
+                exchange,
+            } => {
+                smart_buf.write_u16(*preference)?;
+                smart_buf.write_qname(exchange)?;
+            }
+            RecordData::SOA {
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            } => {
+                smart_buf.write_qname(mname)?;
+                smart_buf.write_qname(rname)?;
+                smart_buf.write_u32(*serial)?;
+                smart_buf.write_u32(*refresh)?;
+                smart_buf.write_u32(*retry)?;
+                smart_buf.write_u32(*expire)?;
+                smart_buf.write_u32(*minimum)?;
+            }
+            RecordData::TXT(strings) => {
+                for string in strings {
+                    smart_buf.write_u8(string.len() as u8)?;
+                    smart_buf.write_slice(string)?;
+                }
+            }
+            RecordData::SRV {
+                priority,
+                weight,
+                port,
+                target,
+            } => {
+                smart_buf.write_u16(*priority)?;
+                smart_buf.write_u16(*weight)?;
+                smart_buf.write_u16(*port)?;
+                smart_buf.write_qname_uncompressed(target)?;
+            }
+            RecordData::CAA { flags, tag, value } => {
+                smart_buf.write_u8(*flags)?;
+                smart_buf.write_u8(tag.len() as u8)?;
+                smart_buf.write_slice(tag)?;
+                smart_buf.write_slice(value)?;
+            }
+            RecordData::Unknown(data) => smart_buf.write_slice(data)?,
+        }
+
+        Ok(())
+    }
+
+    /// Parses RDATA in presentation format. Relative names are resolved
+    /// against `origin`; data of any type may be given in the RFC 3597
+    /// `\# <length> <hex>` form.
+    pub fn from_text(query_type: QueryType, tokens: &[&str], origin: &Name) -> Result<Self> {
+        if tokens.first() == Some(&GENERIC_RDATA_MARKER) {
+            return Self::from_generic_text(query_type, &tokens[1..]);
+        }
+
+        let data = match (query_type, tokens) {
+            (QueryType::A, [address]) => RecordData::A(
+                address
+                    .parse()
+                    .with_context(|| format!("invalid IPv4 address '{address}'"))?,
+            ),
+            (QueryType::AAAA, [address]) => RecordData::AAAA(
+                address
+                    .parse()
+                    .with_context(|| format!("invalid IPv6 address '{address}'"))?,
+            ),
+            (QueryType::NS, [name]) => RecordData::NS(Name::parse(name, origin)?),
+            (QueryType::CNAME, [name]) => RecordData::CNAME(Name::parse(name, origin)?),
+            (QueryType::PTR, [name]) => RecordData::PTR(Name::parse(name, origin)?),
+            (QueryType::MX, [preference, exchange]) => RecordData::MX {
+                preference: parse_number(preference)?,
+                exchange: Name::parse(exchange, origin)?,
+            },
+            (QueryType::SOA, [mname, rname, serial, refresh, retry, expire, minimum]) => {
+                RecordData::SOA {
+                    mname: Name::parse(mname, origin)?,
+                    rname: Name::parse(rname, origin)?,
+                    serial: parse_number(serial)?,
+                    refresh: parse_ttl(refresh)?,
+                    retry: parse_ttl(retry)?,
+                    expire: parse_ttl(expire)?,
+                    minimum: parse_ttl(minimum)?,
+                }
+            }
+            (QueryType::TXT, strings) if !strings.is_empty() => RecordData::TXT(
+                strings
+                    .iter()
+                    .map(|string| parse_character_string(string))
+                    .collect::<Result<_>>()?,
+            ),
+            (QueryType::SRV, [priority, weight, port, target]) => RecordData::SRV {
+                priority: parse_number(priority)?,
+                weight: parse_number(weight)?,
+                port: parse_number(port)?,
+                target: Name::parse(target, origin)?,
+            },
+            (QueryType::CAA, [flags, tag, value]) => {
+                if tag.is_empty() || !tag.bytes().all(|b| b.is_ascii_alphanumeric()) {
+                    bail!("invalid CAA tag '{tag}'");
+                }
+                RecordData::CAA {
+                    flags: parse_number(flags)?,
+                    tag: tag.to_string(),
+                    value: parse_character_string(value)?,
+                }
+            }
+            (QueryType::Unknown(_), _) => {
+                bail!("data of unknown record type {query_type} must use the \\# syntax")
+            }
+            (query_type, _) => bail!("wrong number of fields for {query_type} record"),
+        };
+
+        Ok(data)
+    }
+
+    fn from_generic_text(query_type: QueryType, tokens: &[&str]) -> Result<Self> {
+        let Some((length, hex)) = tokens.split_first() else {
+            bail!("\\# syntax expects a data length");
+        };
+        let length = parse_number::<u16>(length)?;
+
+        let hex = hex.concat();
+        if !hex.is_ascii() {
+            bail!("invalid hex data '{hex}'");
+        }
+        if hex.len() != length as usize * 2 {
+            bail!("\\# data length doesn't match its declared length {length}");
+        }
+        let data = (0..hex.len())
+            .step_by(2)
+            .map(|i| {
+                u8::from_str_radix(&hex[i..i + 2], 16)
+                    .map_err(|_| anyhow!("invalid hex data '{hex}'"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut smart_buf = SmartBuffer::new(data.as_slice());
+        Self::from_bytes(&mut smart_buf, query_type, length)
+    }
+}
+
+impl Display for RecordData {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecordData::A(address) => write!(f, "{address}"),
+            RecordData::AAAA(address) => write!(f, "{address}"),
+            RecordData::NS(name) | RecordData::CNAME(name) | RecordData::PTR(name) => {
+                write!(f, "{name}")
+            }
+            RecordData::MX {
+                preference,
+                exchange,
+            } => write!(f, "{preference} {exchange}"),
+            RecordData::SOA {
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            } => write!(
+                f,
+                "{mname} {rname} {serial} {refresh} {retry} {expire} {minimum}"
+            ),
+            RecordData::TXT(strings) => {
+                let strings = strings
+                    .iter()
+                    .map(|string| format_character_string(string))
+                    .collect::<Vec<_>>();
+                write!(f, "{}", strings.join(" "))
+            }
+            RecordData::SRV {
+                priority,
+                weight,
+                port,
+                target,
+            } => write!(f, "{priority} {weight} {port} {target}"),
+            RecordData::CAA { flags, tag, value } => {
+                write!(f, "{flags} {tag} {}", format_character_string(value))
+            }
+            RecordData::Unknown(data) => {
+                write!(f, "{GENERIC_RDATA_MARKER} {}", data.len())?;
+                if !data.is_empty() {
+                    write!(f, " ")?;
+                    for byte in data {
+                        write!(f, "{byte:02X}")?;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+fn parse_number<N: std::str::FromStr>(text: &str) -> Result<N> {
+    text.parse().map_err(|_| anyhow!("invalid number '{text}'"))
+}
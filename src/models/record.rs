@@ -1,15 +1,17 @@
 use crate::models::enums::{QueryClass, QueryType};
+use crate::models::name::Name;
+use crate::models::rdata::RecordData;
 use crate::smart_buffer::SmartBuffer;
 use anyhow::Result;
+use std::fmt::{Display, Formatter};
 
 #[derive(Clone, Debug)]
 pub struct RawRecord {
-    pub(in crate::models) name: String,
+    pub(in crate::models) name: Name,
     pub(in crate::models) query_type: QueryType,
     pub(in crate::models) query_class: QueryClass,
     pub(in crate::models) ttl: u32,
-    pub(in crate::models) rdata_length: u16,
-    pub(in crate::models) rdata: Vec<u8>,
+    pub(in crate::models) data: RecordData,
 }
 
 impl RawRecord {
@@ -22,15 +24,14 @@ impl RawRecord {
         let query_class = QueryClass::from(smart_buf.read_u16()?);
         let ttl = smart_buf.read_u32()?;
         let rdata_length = smart_buf.read_u16()?;
-        let rdata = smart_buf.read_slice(rdata_length as usize)?.to_vec();
+        let data = RecordData::from_bytes(smart_buf, query_type, rdata_length)?;
 
         Ok(Self {
             name,
             query_type,
             query_class,
             ttl,
-            rdata_length,
-            rdata,
+            data,
         })
     }
 
@@ -42,9 +43,54 @@ impl RawRecord {
         smart_buf.write_u16(u16::from(self.query_type))?;
         smart_buf.write_u16(u16::from(self.query_class))?;
         smart_buf.write_u32(self.ttl)?;
-        smart_buf.write_u16(self.rdata_length)?;
-        smart_buf.write_slice(&self.rdata)?;
+
+        let rdata_length_pos = smart_buf.pos();
+        smart_buf.write_u16(0)?;
+        self.data.to_bytes(smart_buf)?;
+        let rdata_length = smart_buf.pos() - rdata_length_pos - 2;
+        smart_buf.set_u16(rdata_length_pos, rdata_length as u16)?;
 
         Ok(())
     }
+
+    pub(in crate::models) fn is_same_rrset(&self, other: &RawRecord) -> bool {
+        self.name == other.name
+            && self.query_type == other.query_type
+            && self.query_class == other.query_class
+    }
+
+    pub fn name(&self) -> &Name {
+        &self.name
+    }
+
+    pub fn query_type(&self) -> QueryType {
+        self.query_type
+    }
+
+    pub fn query_class(&self) -> QueryClass {
+        self.query_class
+    }
+
+    pub fn ttl(&self) -> u32 {
+        self.ttl
+    }
+
+    pub fn with_ttl(mut self, ttl: u32) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn data(&self) -> &RecordData {
+        &self.data
+    }
+}
+
+impl Display for RawRecord {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} {}",
+            self.name, self.ttl, self.query_class, self.query_type, self.data
+        )
+    }
 }
@@ -0,0 +1,103 @@
+use anyhow::{anyhow, bail, Result};
+use std::fmt::Write;
+
+const MAX_TTL: u64 = i32::MAX as u64;
+const MAX_CHARACTER_STRING_LENGTH: usize = 255;
+
+/// Parses a TTL either as plain seconds or in BIND's `1w2d3h4m5s` notation.
+pub fn parse_ttl(text: &str) -> Result<u32> {
+    let invalid = || anyhow!("invalid TTL '{text}'");
+
+    if let Ok(secs) = text.parse::<u64>() {
+        return u32::try_from(secs.min(MAX_TTL)).map_err(|_| invalid());
+    }
+    // every unit needs a number before it, so there's at least one digit
+    if !text.starts_with(|c: char| c.is_ascii_digit()) {
+        return Err(invalid());
+    }
+
+    let mut total = 0u64;
+    let mut value = None::<u64>;
+    for c in text.chars() {
+        if let Some(digit) = c.to_digit(10) {
+            value = Some(value.unwrap_or(0) * 10 + digit as u64);
+            if value > Some(MAX_TTL) {
+                return Err(invalid());
+            }
+            continue;
+        }
+
+        let multiplier = match c.to_ascii_lowercase() {
+            'w' => 7 * 24 * 60 * 60,
+            'd' => 24 * 60 * 60,
+            'h' => 60 * 60,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(invalid()),
+        };
+        total += value.take().ok_or_else(invalid)? * multiplier;
+    }
+    total += value.unwrap_or(0);
+
+    u32::try_from(total.min(MAX_TTL)).map_err(|_| invalid())
+}
+
+/// Parses an RFC 1035 `<character-string>`, resolving `\X` and `\DDD` escapes.
+pub fn parse_character_string(text: &str) -> Result<Vec<u8>> {
+    let mut result = Vec::with_capacity(text.len());
+
+    let mut bytes = text.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte != b'\\' {
+            result.push(byte);
+            continue;
+        }
+
+        let escaped = bytes
+            .next()
+            .ok_or_else(|| anyhow!("dangling escape in '{text}'"))?;
+        if escaped.is_ascii_digit() {
+            let digits = [
+                escaped,
+                bytes.next().unwrap_or(0),
+                bytes.next().unwrap_or(0),
+            ];
+            let code = std::str::from_utf8(&digits)
+                .ok()
+                .and_then(|digits| digits.parse::<u8>().ok())
+                .ok_or_else(|| anyhow!("invalid \\DDD escape in '{text}'"))?;
+            result.push(code);
+        } else {
+            result.push(escaped);
+        }
+    }
+
+    if result.len() > MAX_CHARACTER_STRING_LENGTH {
+        bail!("character string longer than {MAX_CHARACTER_STRING_LENGTH} bytes");
+    }
+
+    Ok(result)
+}
+
+/// Formats a `<character-string>` as a quoted string, escaping everything
+/// that's not printable ASCII.
+pub fn format_character_string(bytes: &[u8]) -> String {
+    let mut result = String::with_capacity(bytes.len() + 2);
+
+    result.push('"');
+    for &byte in bytes {
+        match byte {
+            b'"' | b'\\' => {
+                result.push('\\');
+                result.push(byte as char);
+            }
+            0x20..=0x7E => result.push(byte as char),
+            _ => {
+                let _ = write!(result, "\\{byte:03}");
+            }
+        }
+    }
+    result.push('"');
+
+    result
+}
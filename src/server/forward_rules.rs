@@ -0,0 +1,50 @@
+use crate::config::Config;
+use crate::models::Name;
+use crate::server::UpstreamGroup;
+use anyhow::Result;
+
+struct ForwardRule {
+    suffix: Name,
+    upstreams: UpstreamGroup,
+}
+
+/// Per-domain upstreams, the most specific suffix matching a name wins.
+pub struct ForwardRules {
+    /// Sorted by decreasing label count, so the first match is the longest.
+    rules: Vec<ForwardRule>,
+}
+
+impl ForwardRules {
+    pub fn new(config: &Config) -> Result<Self> {
+        let mut rules = config
+            .forward
+            .iter()
+            .map(|rule| {
+                Ok(ForwardRule {
+                    suffix: rule.suffix.parse()?,
+                    upstreams: UpstreamGroup::new(
+                        &rule.servers,
+                        rule.forwarder_options(&config.upstream),
+                        config.upstream.health_options(),
+                    ),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        rules.sort_by_key(|rule| std::cmp::Reverse(rule.suffix.label_count()));
+
+        Ok(Self { rules })
+    }
+
+    pub fn find(&self, name: &Name) -> Option<(&Name, &UpstreamGroup)> {
+        self.rules
+            .iter()
+            .find(|rule| name.is_subdomain_of(&rule.suffix))
+            .map(|rule| (&rule.suffix, &rule.upstreams))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Name, &UpstreamGroup)> {
+        self.rules
+            .iter()
+            .map(|rule| (&rule.suffix, &rule.upstreams))
+    }
+}
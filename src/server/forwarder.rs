@@ -0,0 +1,213 @@
+use crate::models::{
+    new_packet_buffer, DnsPacket, DnsPacketBuilder, Edns, Question, DEFAULT_UDP_PAYLOAD_SIZE,
+};
+use anyhow::{bail, Result};
+use crossbeam::queue::ArrayQueue;
+use rand::random;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::time::{Duration, Instant};
+
+const MIN_SOURCE_PORT: u16 = 1024;
+const MAX_BIND_ATTEMPTS: usize = 16;
+
+const DEFAULT_TIMEOUT_MS: u64 = 2000;
+const DEFAULT_RETRIES: usize = 2;
+const DEFAULT_SOCKET_POOL_SIZE: usize = 32;
+
+#[derive(Clone, Debug)]
+pub struct ForwarderOptions {
+    pub timeout: Duration,
+    pub retries: usize,
+    pub socket_pool_size: usize,
+    /// Cleared when talking to authoritative servers.
+    pub recursion_desired: bool,
+}
+
+impl Default for ForwarderOptions {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_millis(DEFAULT_TIMEOUT_MS),
+            retries: DEFAULT_RETRIES,
+            socket_pool_size: DEFAULT_SOCKET_POOL_SIZE,
+            recursion_desired: true,
+        }
+    }
+}
+
+/// Sends queries to upstream servers and waits for the matching response.
+///
+/// Every query gets a random ID and goes out of a socket bound to a random
+/// port. Datagrams coming from another address or not matching the query's
+/// ID and question are dropped, so a spoofed answer has to guess both.
+pub struct Forwarder {
+    options: ForwarderOptions,
+    ipv4_sockets: ArrayQueue<UdpSocket>,
+    ipv6_sockets: ArrayQueue<UdpSocket>,
+}
+
+impl Forwarder {
+    pub fn new(options: ForwarderOptions) -> Self {
+        let pool_size = options.socket_pool_size.max(1);
+
+        Self {
+            options,
+            ipv4_sockets: ArrayQueue::new(pool_size),
+            ipv6_sockets: ArrayQueue::new(pool_size),
+        }
+    }
+
+    pub fn query(&self, server: SocketAddr, question: &Question) -> Result<DnsPacket> {
+        for attempt in 0..=self.options.retries {
+            match self.query_once(server, question) {
+                Ok(Some(response)) if response.is_truncated() => {
+                    log::debug!("truncated response from {server}, retrying over tcp");
+                    return self.query_tcp(server, question);
+                }
+                Ok(Some(response)) => return Ok(response),
+                Ok(None) => log::debug!(
+                    "upstream {server} timed out (attempt {} of {})",
+                    attempt + 1,
+                    self.options.retries + 1
+                ),
+                Err(e) => return Err(e),
+            }
+        }
+
+        bail!("upstream {server} timed out")
+    }
+
+    /// Returns `Ok(None)` on timeout. The socket is closed then rather than
+    /// returned to the pool, so a late response can't reach the next query.
+    fn query_once(&self, server: SocketAddr, question: &Question) -> Result<Option<DnsPacket>> {
+        let id = random();
+        let request = self.request(id, question);
+
+        let mut buf = new_packet_buffer(DEFAULT_UDP_PAYLOAD_SIZE);
+        let len = request.to_bytes(&mut buf)?;
+
+        let socket = self.take_socket(server)?;
+        socket.send_to(&buf[..len], server)?;
+
+        let deadline = Instant::now() + self.options.timeout;
+        loop {
+            let Some(timeout) = deadline
+                .checked_duration_since(Instant::now())
+                .filter(|timeout| !timeout.is_zero())
+            else {
+                return Ok(None);
+            };
+            socket.set_read_timeout(Some(timeout))?;
+
+            let src = match socket.recv_from(&mut buf) {
+                Ok((_, src)) => src,
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    return Ok(None);
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+            if src != server {
+                log::warn!("dropping response from unexpected source {src}, expected {server}");
+                continue;
+            }
+
+            let response = match DnsPacket::from_bytes(&buf) {
+                Ok(response) => response,
+                Err(e) => {
+                    log::warn!("dropping malformed response from {server}: {e}");
+                    continue;
+                }
+            };
+
+            if !Self::is_response_to(&response, id, question) {
+                log::warn!("dropping response from {server} not matching the query");
+                continue;
+            }
+
+            self.return_socket(socket);
+            return Ok(Some(response));
+        }
+    }
+
+    fn query_tcp(&self, server: SocketAddr, question: &Question) -> Result<DnsPacket> {
+        let id = random();
+        let request = self.request(id, question);
+
+        let mut buf = new_packet_buffer(DEFAULT_UDP_PAYLOAD_SIZE);
+        let len = request.to_bytes(&mut buf[2..])?;
+        buf[..2].copy_from_slice(&(len as u16).to_be_bytes());
+
+        let mut stream = TcpStream::connect_timeout(&server, self.options.timeout)?;
+        stream.set_read_timeout(Some(self.options.timeout))?;
+        stream.set_write_timeout(Some(self.options.timeout))?;
+        stream.write_all(&buf[..len + 2])?;
+
+        let mut len = [0u8; 2];
+        stream.read_exact(&mut len)?;
+        let mut message = vec![0u8; u16::from_be_bytes(len) as usize];
+        stream.read_exact(&mut message)?;
+
+        let response = DnsPacket::from_bytes(&message)?;
+        if !Self::is_response_to(&response, id, question) {
+            bail!("tcp response from {server} not matching the query");
+        }
+
+        Ok(response)
+    }
+
+    fn request(&self, id: u16, question: &Question) -> DnsPacket {
+        DnsPacketBuilder::default()
+            .id(id)
+            .recursion_desired(self.options.recursion_desired)
+            .with_question(question.clone())
+            .edns(Edns::new(DEFAULT_UDP_PAYLOAD_SIZE))
+            .build()
+    }
+
+    fn is_response_to(response: &DnsPacket, id: u16, question: &Question) -> bool {
+        response.is_response()
+            && response.id() == id
+            && matches!(response.questions(), [answered] if answered.matches(question))
+    }
+
+    fn pool(&self, server: SocketAddr) -> &ArrayQueue<UdpSocket> {
+        match server {
+            SocketAddr::V4(_) => &self.ipv4_sockets,
+            SocketAddr::V6(_) => &self.ipv6_sockets,
+        }
+    }
+
+    fn take_socket(&self, server: SocketAddr) -> Result<UdpSocket> {
+        match self.pool(server).pop() {
+            Some(socket) => Ok(socket),
+            None => Self::bind_random_port(server),
+        }
+    }
+
+    fn return_socket(&self, socket: UdpSocket) {
+        let Ok(local_addr) = socket.local_addr() else {
+            return;
+        };
+        // a full pool just closes the socket
+        let _ = self.pool(local_addr).push(socket);
+    }
+
+    fn bind_random_port(server: SocketAddr) -> Result<UdpSocket> {
+        for _ in 0..MAX_BIND_ATTEMPTS {
+            let port = MIN_SOURCE_PORT + random::<u16>() % (u16::MAX - MIN_SOURCE_PORT);
+            let local = match server {
+                SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)),
+                SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, port)),
+            };
+
+            match UdpSocket::bind(local) {
+                Ok(socket) => return Ok(socket),
+                Err(e) if e.kind() == ErrorKind::AddrInUse => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        bail!("failed binding a random source port")
+    }
+}
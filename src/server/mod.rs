@@ -1,35 +1,179 @@
-use crate::cache::{CacheItemPolicy, MemoryCache};
+mod forward_rules;
+mod forwarder;
+mod recursor;
+mod tcp;
+mod upstreams;
+
+pub use forward_rules::ForwardRules;
+pub use forwarder::{Forwarder, ForwarderOptions};
+pub use recursor::{Recursor, RecursorOptions};
+pub use tcp::TcpOptions;
+pub use upstreams::{HealthOptions, UpstreamGroup, UpstreamStats};
+
+use crate::cache::{CacheOptions, DnsCache};
+use crate::config::Config;
 use crate::models::{
-    new_packet_buffer, DnsPacket, DnsPacketBase, DnsPacketBuilder, MessageType, QueryClass,
-    QueryType, Question, RawRecordType, ResultCode,
+    new_packet_buffer, DnsPacket, DnsPacketBuilder, Edns, MessageType, Name, QueryClass, QueryType,
+    Question, RawRecordType, ResultCode, EDE_STALE_ANSWER, EDNS_VERSION, MIN_UDP_PAYLOAD_SIZE,
 };
+use crate::zone::ZoneStore;
 use anyhow::{bail, Context, Result};
 use crossbeam::channel as mpmc;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
-use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
-use std::sync::Arc;
+use rustc_hash::FxHashSet;
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
+use tcp::{ConnectionLimiter, TcpConnection};
+
+const DEFAULT_STALE_ANSWER_TTL: u32 = 30;
+const DEFAULT_CLIENT_RESPONSE_TIMEOUT_MS: u64 = 1800;
+const STALE_REFRESH_MIN_BACKOFF: Duration = Duration::from_secs(1);
+const STALE_REFRESH_MAX_BACKOFF: Duration = Duration::from_secs(30);
+const PREFETCH_WORKERS: usize = 2;
+/// Prefetches beyond it are dropped, the entries expire as usual.
+const PREFETCH_QUEUE_SIZE: usize = 64;
+/// Share of the cache memory budget for the recursor's delegations.
+const DELEGATION_BUDGET_PERCENT: usize = 10;
+
+type Request = (DnsPacket, Responder);
+
+/// Where the response to a request goes.
+enum Responder {
+    Udp { socket_idx: usize, src: SocketAddr },
+    Tcp(Arc<TcpConnection>),
+}
+
+/// Answering from expired cache entries when the upstreams fail or take too
+/// long (RFC 8767).
+#[derive(Clone, Debug)]
+pub struct ServeStaleOptions {
+    pub answer_ttl: u32,
+    /// How long a client waits for the upstreams before it gets stale data.
+    pub client_response_timeout: Duration,
+}
 
-const BROKEN_BIND_FILE_ERROR_MSG: &str = "broken bind file";
+impl Default for ServeStaleOptions {
+    fn default() -> Self {
+        Self {
+            answer_ttl: DEFAULT_STALE_ANSWER_TTL,
+            client_response_timeout: Duration::from_millis(DEFAULT_CLIENT_RESPONSE_TIMEOUT_MS),
+        }
+    }
+}
 
 pub struct DnsServer {
-    socket: UdpSocket,
-    cache: MemoryCache<String, DnsPacketBase>,
+    sockets: Vec<UdpSocket>,
+    tcp_listeners: Vec<TcpListener>,
+    tcp_options: TcpOptions,
+    max_udp_payload_size: u16,
+    connection_limiter: Arc<ConnectionLimiter>,
+    upstreams: UpstreamGroup,
+    forward_rules: ForwardRules,
+    recursor: Option<Recursor>,
+    stats_interval: Duration,
+    zones: ZoneStore,
+    cache: DnsCache,
+    serve_stale: Option<ServeStaleOptions>,
+    /// Questions whose stale answer is being refreshed in the background.
+    refreshing: Mutex<FxHashSet<(Name, QueryType, QueryClass)>>,
+    prefetch_queue: mpmc::Sender<Question>,
+    prefetch_jobs: mpmc::Receiver<Question>,
+    cache_snapshot: Option<PathBuf>,
+    snapshot_interval: Duration,
 }
 
 impl DnsServer {
-    pub fn new() -> Result<Self> {
-        // TODO: port from config
-        let socket = UdpSocket::bind(("0.0.0.0", 53))?;
-        let cache = MemoryCache::new();
+    pub fn new(config: &Config) -> Result<Self> {
+        let mut sockets = Vec::with_capacity(config.server.listen.len());
+        let mut tcp_listeners = Vec::with_capacity(config.server.listen.len());
+        for addr in &config.server.listen {
+            let socket = UdpSocket::bind(addr).with_context(|| format!("failed binding {addr}"))?;
+            // the bound address, so a random port is the same for both
+            let addr = socket.local_addr()?;
+            let listener =
+                TcpListener::bind(addr).with_context(|| format!("failed binding tcp {addr}"))?;
+
+            sockets.push(socket);
+            tcp_listeners.push(listener);
+        }
+        let tcp_options = config.server.tcp_options();
+
+        let zones = ZoneStore::load(&config.zones)?;
+        log::info!("serving {} local records", zones.records_count());
+
+        let (prefetch_queue, prefetch_jobs) = mpmc::bounded(PREFETCH_QUEUE_SIZE);
 
-        Ok(Self { socket, cache })
+        let cache_options = config.cache.options();
+        let cache = DnsCache::new(if config.recursion.enabled {
+            cache_options.with_budget_share(100 - DELEGATION_BUDGET_PERCENT)
+        } else {
+            cache_options.clone()
+        });
+        if let Some(path) = config
+            .cache
+            .snapshot_file
+            .as_ref()
+            .filter(|path| path.exists())
+        {
+            match cache.load_snapshot(path) {
+                Ok(count) => log::info!("restored {count} cache entries from {}", path.display()),
+                Err(e) => log::warn!("failed restoring the cache: {e:#}"),
+            }
+        }
+
+        Ok(Self {
+            sockets,
+            tcp_listeners,
+            connection_limiter: Arc::new(ConnectionLimiter::new(
+                tcp_options.max_connections_per_client,
+            )),
+            tcp_options,
+            max_udp_payload_size: config.server.max_udp_payload_size,
+            upstreams: UpstreamGroup::new(
+                &config.upstream.servers,
+                config.upstream.forwarder_options(),
+                config.upstream.health_options(),
+            ),
+            forward_rules: ForwardRules::new(config)?,
+            recursor: config.recursion.enabled.then(|| {
+                Recursor::new(
+                    config.recursion.options(),
+                    config.upstream.socket_pool_size,
+                    // delegations are never served stale nor prefetched
+                    CacheOptions {
+                        max_stale: Duration::ZERO,
+                        prefetch_threshold_percent: 0,
+                        ..cache_options.with_budget_share(DELEGATION_BUDGET_PERCENT)
+                    },
+                )
+            }),
+            stats_interval: Duration::from_secs(config.upstream.stats_interval_secs),
+            zones,
+            cache,
+            serve_stale: config.cache.serve_stale_options(),
+            refreshing: Mutex::default(),
+            prefetch_queue,
+            prefetch_jobs,
+            cache_snapshot: config.cache.snapshot_file.clone(),
+            snapshot_interval: Duration::from_secs(config.cache.snapshot_interval_secs),
+        })
+    }
+
+    /// The addresses listened on, with the ports picked for `:0` ones.
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.sockets
+            .iter()
+            .filter_map(|socket| socket.local_addr().ok())
+            .collect()
     }
 
     pub fn run(self, num_workers: usize) -> Result<()> {
         log::info!("starting server with {num_workers} workers");
+        for addr in self.local_addrs() {
+            log::info!("listening on {addr}");
+        }
 
         let (tx, rx) = mpmc::unbounded();
 
@@ -41,7 +185,48 @@ impl DnsServer {
             join_handles.push(thread::spawn(|| this.lookup_job(rx)));
         }
 
-        this.handle_requests(tx)?;
+        let mut listen_handles = Vec::with_capacity(this.sockets.len());
+        for socket_idx in 0..this.sockets.len() {
+            let tx = tx.clone();
+            let this = Arc::clone(&this);
+            listen_handles.push(thread::spawn(move || this.handle_requests(socket_idx, tx)));
+        }
+        for listener_idx in 0..this.tcp_listeners.len() {
+            let tx = tx.clone();
+            let this = Arc::clone(&this);
+            listen_handles.push(thread::spawn(move || {
+                this.accept_connections(listener_idx, tx)
+            }));
+        }
+        drop(tx);
+
+        for _ in 0..PREFETCH_WORKERS {
+            let this = Arc::clone(&this);
+            thread::spawn(move || this.prefetch_job());
+        }
+
+        if !this.stats_interval.is_zero() {
+            let this = Arc::clone(&this);
+            thread::spawn(move || this.log_stats_job());
+        }
+
+        if this.cache_snapshot.is_some() {
+            let job = Arc::clone(&this);
+            thread::spawn(move || job.snapshot_job());
+
+            let on_shutdown = Arc::clone(&this);
+            ctrlc::set_handler(move || {
+                on_shutdown.save_cache();
+                std::process::exit(0);
+            })
+            .context("failed setting the shutdown handler")?;
+        }
+
+        for handle in listen_handles {
+            if let Err(e) = handle.join().expect("failed joining thread") {
+                log::error!("error while listening: {e}");
+            }
+        }
 
         for handle in join_handles {
             if let Err(e) = handle.join().expect("failed joining thread") {
@@ -52,203 +237,456 @@ impl DnsServer {
         Ok(())
     }
 
-    // TODO: recursive-lookup
-    fn lookup_redirect(&self, id: u16, question: &Question) -> Result<DnsPacket> {
-        // TODO: from config
-        let server = ("8.8.8.8", 53);
-        let socket = UdpSocket::bind(("0.0.0.0", 43210))?;
-
-        let request = DnsPacketBuilder::default()
-            .id(id)
-            .recursion_desired(true)
-            .with_question(question.clone())
-            .build();
-
-        let mut buf = new_packet_buffer();
-        request.to_bytes(&mut buf)?;
-
-        socket.send_to(&buf, server)?;
-
-        socket.recv_from(&mut buf)?;
+    pub fn upstream_stats(&self) -> Vec<UpstreamStats> {
+        self.upstreams.stats()
+    }
 
-        DnsPacket::from_bytes(&buf)
+    fn lookup_redirect(&self, question: &Question) -> Result<DnsPacket> {
+        match &self.recursor {
+            Some(recursor) => recursor.resolve(question),
+            None => self.upstreams.query(question),
+        }
     }
 
+    /// Answers from the zones, the end of a CNAME chain leaving them is
+    /// looked up in the cache or upstream.
     fn lookup_local(&self, request: &DnsPacket) -> Result<Option<DnsPacket>> {
         let question = request.questions().first().unwrap();
 
-        let mut response_builder = Self::default_response_request_builder_from(request);
-
-        // TODO: move path to bind in config
-        let file = File::open("bind.txt")?;
-        let mut reader = BufReader::new(file);
-
-        let mut found = false;
-        let mut buf = String::with_capacity(512);
-        while reader.read_line(&mut buf)? > 0 {
-            let tokens = buf.split_whitespace().collect::<Vec<_>>();
-
-            if tokens.is_empty() || tokens[0] == ";" || tokens[0].as_bytes()[0] == b';' {
-                continue;
-            }
-
-            if tokens.len() != 4 {
-                bail!(BROKEN_BIND_FILE_ERROR_MSG);
-            }
-
-            if tokens[0] == question.name() {
-                let name = tokens[0];
-                let q_class =
-                    QueryClass::try_from(tokens[1]).context(BROKEN_BIND_FILE_ERROR_MSG)?;
-                let q_type = QueryType::try_from(tokens[2]).context(BROKEN_BIND_FILE_ERROR_MSG)?;
-
-                // TODO: A-records now only, add more
-                let rdata = tokens[3]
-                    .parse::<Ipv4Addr>()
-                    .context(BROKEN_BIND_FILE_ERROR_MSG)?
-                    .octets();
+        let Some(answer) = self.zones.answer(question) else {
+            return Ok(None);
+        };
 
+        let mut response_builder = self
+            .default_response_request_builder_from(request)
+            .authoritative_answer(answer.authoritative)
+            .result_code(answer.result_code);
+
+        for (records, record_type) in [
+            (&answer.answers, RawRecordType::Answer),
+            (&answer.authorities, RawRecordType::Authority),
+            (&answer.additional, RawRecordType::Additional),
+        ] {
+            for record in records {
                 response_builder = response_builder
                     .new_raw_record()
-                    .name(name)
-                    .query_class(q_class)
-                    .query_type(q_type)
-                    .ttl(300)
-                    .rdata(rdata.to_vec())
-                    .add_raw_record(RawRecordType::Answer)?;
-
-                found = true;
+                    .name(record.name.clone())
+                    .query_class(record.query_class)
+                    .query_type(record.query_type)
+                    .ttl(record.ttl)
+                    .data(record.data.clone())
+                    .add_raw_record(record_type)?;
             }
-
-            buf.clear();
         }
 
-        if found {
-            Ok(Some(response_builder.build()))
-        } else {
-            Ok(None)
+        if let Some(target) = answer.chase {
+            let question = Question::new(target, question.query_type(), question.query_class());
+            let (result_code, answers, authorities) = match self.cache.lookup(&question) {
+                Some(cached) => {
+                    if cached.prefetch {
+                        self.prefetch(question);
+                    }
+                    (cached.result_code, cached.answers, cached.authorities)
+                }
+                None => {
+                    let result = self.resolve(&question)?;
+                    (
+                        result.result_code(),
+                        result.answers().to_vec(),
+                        result.authorities().to_vec(),
+                    )
+                }
+            };
+            response_builder = response_builder
+                .result_code(result_code)
+                .with_answers(answers)
+                .with_authorities(authorities);
         }
+
+        Ok(Some(response_builder.build()))
     }
 
     fn lookup_cache(&self, request: &DnsPacket) -> Option<DnsPacket> {
         let question = request.questions().first().unwrap();
 
-        self.cache.get(question.name()).map(|cached| {
-            Self::default_response_request_builder_from(request)
-                .with_base(cached.as_ref().clone())
+        self.cache.lookup(question).map(|cached| {
+            if cached.prefetch {
+                self.prefetch(question.clone());
+            }
+            self.default_response_request_builder_from(request)
+                .result_code(cached.result_code)
+                .with_answers(cached.answers)
+                .with_authorities(cached.authorities)
                 .build()
         })
     }
 
-    fn try_lookup(&self, request: &DnsPacket) -> Result<DnsPacket> {
+    /// Answers from expired cache entries, flagged with an Extended DNS
+    /// Error so clients can tell.
+    fn lookup_stale(&self, request: &DnsPacket) -> Option<DnsPacket> {
+        let options = self.serve_stale.as_ref()?;
         let question = request.questions().first().unwrap();
 
-        let response = if let Some(result) = self.lookup_cache(request) {
-            log::info!("found response in cache");
-            result
-        } else if let Some(result) = self.lookup_local(request)? {
+        self.cache
+            .lookup_stale(question, options.answer_ttl)
+            .map(|cached| {
+                let mut builder = self
+                    .default_response_request_builder_from(request)
+                    .result_code(cached.result_code)
+                    .with_answers(cached.answers)
+                    .with_authorities(cached.authorities);
+                if let Some(edns) = self.response_edns(request) {
+                    builder = builder.edns(edns.with_extended_error(EDE_STALE_ANSWER));
+                }
+                builder.build()
+            })
+    }
+
+    /// Asks the upstreams responsible for the question and caches the result.
+    fn resolve(&self, question: &Question) -> Result<DnsPacket> {
+        let result = match self.forward_rules.find(question.name()) {
+            Some((suffix, upstreams)) => {
+                log::info!("forwarding {} by the rule for {suffix}", question.name());
+                upstreams.query(question)?
+            }
+            None => self.lookup_redirect(question)?,
+        };
+        for answer in result.answers() {
+            log::debug!("upstream answer: {answer}");
+        }
+
+        log::info!(
+            "found response on another dns-server. caching redirected result for {}",
+            question.name()
+        );
+        self.cache.insert_response(question, &result);
+
+        Ok(result)
+    }
+
+    /// Queues a popular cache entry to be refreshed in the background before
+    /// it expires.
+    fn prefetch(&self, question: Question) {
+        log::info!("prefetching {}", question.name());
+
+        if let Err(e) = self.prefetch_queue.try_send(question) {
+            let question = e.into_inner();
+            log::warn!("too many prefetches, skipping {}", question.name());
+            self.cache.release_prefetch(&question);
+        }
+    }
+
+    /// Gives the upstreams until the client response timer runs out to
+    /// refresh a stale answer. The refresh goes on in the background after
+    /// that, only one at a time per question, retried with backoff until it
+    /// succeeds or the stale entry is gone.
+    fn resolve_or_serve_stale(
+        self: &Arc<Self>,
+        request: &DnsPacket,
+        stale: DnsPacket,
+        options: &ServeStaleOptions,
+    ) -> DnsPacket {
+        let question = request.questions().first().unwrap().clone();
+        let key = (
+            question.name().clone(),
+            question.query_type(),
+            question.query_class(),
+        );
+
+        if !self.refreshing.lock().unwrap().insert(key.clone()) {
             log::info!(
-                "found response in local storage. caching result for {}",
+                "serving stale answer for {} while refreshing it",
                 question.name()
             );
-            self.cache_response_without_ttl(question, &result);
-            result
-        } else {
-            let result = self.lookup_redirect(request.id(), question)?;
+            self.cache.count_stale_hit();
+            return stale;
+        }
 
-            if result.result_code() == ResultCode::NoError {
-                log::info!(
-                    "found response on another dns-server. caching redirected result for {}",
-                    question.name()
+        let (tx, rx) = mpmc::bounded(1);
+        let this = Arc::clone(self);
+        let answer_ttl = options.answer_ttl;
+        thread::spawn(move || {
+            let mut tx = Some(tx);
+            let mut backoff = STALE_REFRESH_MIN_BACKOFF;
+            loop {
+                let result = this.resolve(&question);
+                let refreshed = matches!(
+                    &result,
+                    Ok(result) if result.result_code() != ResultCode::ServerFailure
                 );
-                self.cache_response_with_ttl(question, &result);
+                if let Some(tx) = tx.take() {
+                    let _ = tx.send(result);
+                }
+                if refreshed {
+                    break;
+                }
+
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(STALE_REFRESH_MAX_BACKOFF);
+                if this.cache.lookup_stale(&question, answer_ttl).is_none() {
+                    log::warn!("gave up refreshing {}", question.name());
+                    break;
+                }
+                log::info!("retrying to refresh {}", question.name());
             }
+            this.refreshing.lock().unwrap().remove(&key);
+        });
+
+        match rx.recv_timeout(options.client_response_timeout) {
+            Ok(Ok(result)) if result.result_code() != ResultCode::ServerFailure => {
+                return self.response_from(request, &result);
+            }
+            Ok(Ok(_)) => log::warn!("upstream failed, serving stale answer"),
+            Ok(Err(e)) => log::warn!("failed looking-up, serving stale answer: {e}"),
+            Err(_) => log::info!("upstream too slow, serving stale answer"),
+        }
+        self.cache.count_stale_hit();
+
+        stale
+    }
+
+    fn try_lookup(self: &Arc<Self>, request: &DnsPacket) -> Result<DnsPacket> {
+        let question = request.questions().first().unwrap();
+
+        let response = if let Some(result) = self.lookup_local(request)? {
+            log::info!("found response in local storage for {}", question.name());
             result
+        } else if let Some(result) = self.lookup_cache(request) {
+            log::info!("found response in cache");
+            result
+        } else if let (Some(options), Some(stale)) = (&self.serve_stale, self.lookup_stale(request))
+        {
+            self.resolve_or_serve_stale(request, stale, options)
+        } else {
+            let result = self.resolve(question)?;
+            self.response_from(request, &result)
         };
 
         Ok(response)
     }
 
-    fn lookup(&self, request: DnsPacket, src: SocketAddr) -> Result<()> {
-        let response = if !request.questions().is_empty() {
+    /// The response to `request` carrying what the upstreams answered.
+    fn response_from(&self, request: &DnsPacket, result: &DnsPacket) -> DnsPacket {
+        self.default_response_request_builder_from(request)
+            .result_code(result.result_code())
+            .with_base(result.base().clone())
+            .build()
+    }
+
+    fn lookup(self: &Arc<Self>, request: DnsPacket, responder: Responder) -> Result<()> {
+        let response = if let Some(e) = request.opt_error() {
+            log::warn!("malformed request: {e}");
+            self.default_response_request_builder_from(&request)
+                .result_code(ResultCode::FormatError)
+                .build()
+        } else if request
+            .edns()
+            .is_some_and(|edns| edns.version() != EDNS_VERSION)
+        {
+            self.default_response_request_builder_from(&request)
+                .result_code(ResultCode::BadVersion)
+                .build()
+        } else if !request.questions().is_empty() {
             match self.try_lookup(&request) {
                 Ok(result) => result,
                 Err(e) => {
                     log::error!("failed looking-up: {e}");
-                    Self::default_response_request_builder_from(&request)
+                    self.default_response_request_builder_from(&request)
                         .result_code(ResultCode::ServerFailure)
                         .build()
                 }
             }
         } else {
-            Self::default_response_request_builder_from(&request)
+            self.default_response_request_builder_from(&request)
                 .result_code(ResultCode::FormatError)
                 .build()
         };
 
-        let mut buf = new_packet_buffer();
-        response.to_bytes(&mut buf)?;
-
-        self.socket.send_to(&buf, src)?;
+        match responder {
+            Responder::Udp { socket_idx, src } => {
+                let mut buf = new_packet_buffer(self.udp_payload_size(&request));
+                let len = response.to_bytes(&mut buf)?;
+                self.sockets[socket_idx].send_to(&buf[..len], src)?;
+            }
+            Responder::Tcp(connection) => connection.send(&response)?,
+        }
 
         Ok(())
     }
 
-    fn lookup_job(
-        self: Arc<Self>,
-        rx_requests: mpmc::Receiver<(DnsPacket, SocketAddr)>,
-    ) -> Result<()> {
+    fn lookup_job(self: Arc<Self>, rx_requests: mpmc::Receiver<Request>) -> Result<()> {
         loop {
             match rx_requests.recv() {
-                Ok((request, src)) => self.lookup(request, src)?,
+                Ok((request, responder)) => {
+                    if let Err(e) = self.lookup(request, responder) {
+                        log::error!("failed responding: {e}");
+                    }
+                }
                 Err(e) => bail!("channel disconnected: {e:#?}"),
             }
         }
     }
 
-    fn process_request(&self, tx: &mpmc::Sender<(DnsPacket, SocketAddr)>) -> Result<()> {
-        let mut buf = new_packet_buffer();
-        let (_, src) = self.socket.recv_from(&mut buf)?;
+    fn prefetch_job(self: Arc<Self>) {
+        for question in self.prefetch_jobs.iter() {
+            let refreshed = match self.resolve(&question) {
+                Ok(result) => result.result_code() != ResultCode::ServerFailure,
+                Err(e) => {
+                    log::warn!("failed prefetching {}: {e}", question.name());
+                    false
+                }
+            };
+            if !refreshed {
+                self.cache.release_prefetch(&question);
+            }
+        }
+    }
+
+    fn log_stats_job(self: Arc<Self>) {
+        loop {
+            thread::sleep(self.stats_interval);
+            log::info!("cache {}", self.cache.stats());
+            for stats in self.upstream_stats() {
+                log::info!("upstream {stats}");
+            }
+            for (suffix, upstreams) in self.forward_rules.iter() {
+                for stats in upstreams.stats() {
+                    log::info!("upstream for {suffix} {stats}");
+                }
+            }
+        }
+    }
+
+    fn snapshot_job(self: Arc<Self>) {
+        loop {
+            thread::sleep(self.snapshot_interval);
+            self.save_cache();
+        }
+    }
+
+    fn save_cache(&self) {
+        let Some(path) = &self.cache_snapshot else {
+            return;
+        };
+
+        match self.cache.save_snapshot(path) {
+            Ok(count) => log::info!("saved {count} cache entries to {}", path.display()),
+            Err(e) => log::error!("failed saving the cache: {e:#}"),
+        }
+    }
+
+    fn process_request(&self, socket_idx: usize, tx: &mpmc::Sender<Request>) -> Result<()> {
+        let mut buf = new_packet_buffer(self.max_udp_payload_size);
+        let (_, src) = self.sockets[socket_idx].recv_from(&mut buf)?;
 
         let request = DnsPacket::from_bytes(&buf)?;
 
         log::info!("received request from {src}");
 
-        Ok(tx.send((request, src))?)
+        Ok(tx.send((request, Responder::Udp { socket_idx, src }))?)
+    }
+
+    fn accept_connections(
+        self: Arc<Self>,
+        listener_idx: usize,
+        tx: mpmc::Sender<Request>,
+    ) -> Result<()> {
+        let listener = &self.tcp_listeners[listener_idx];
+        log::info!("tcp server started on {}", listener.local_addr()?);
+
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    log::error!("failed accepting connection: {e}");
+                    continue;
+                }
+            };
+            let Ok(peer) = stream.peer_addr() else {
+                continue;
+            };
+
+            let Some(slot) = self.connection_limiter.acquire(peer.ip()) else {
+                log::warn!("too many connections from {}, refusing", peer.ip());
+                continue;
+            };
+
+            let this = Arc::clone(&self);
+            let tx = tx.clone();
+            thread::spawn(move || {
+                if let Err(e) = this.serve_connection(stream, &tx) {
+                    log::warn!("closing connection from {peer}: {e}");
+                }
+                drop(slot);
+            });
+        }
+
+        Ok(())
+    }
+
+    fn serve_connection(&self, mut stream: TcpStream, tx: &mpmc::Sender<Request>) -> Result<()> {
+        let connection = Arc::new(TcpConnection::new(&stream, &self.tcp_options)?);
+
+        while let Some(message) = connection.read_message(&mut stream)? {
+            let request = match DnsPacket::from_bytes(&message) {
+                Ok(request) => request,
+                Err(e) => {
+                    connection.discard();
+                    bail!("malformed request: {e}");
+                }
+            };
+
+            log::info!("received tcp request from {}", connection.peer());
+
+            tx.send((request, Responder::Tcp(Arc::clone(&connection))))?;
+        }
+
+        Ok(())
     }
 
-    fn handle_requests(&self, tx: mpmc::Sender<(DnsPacket, SocketAddr)>) -> Result<()> {
-        log::info!("server started");
+    fn handle_requests(&self, socket_idx: usize, tx: mpmc::Sender<Request>) -> Result<()> {
+        log::info!(
+            "server started on {}",
+            self.sockets[socket_idx].local_addr()?
+        );
         loop {
-            if let Err(e) = self.process_request(&tx) {
+            if let Err(e) = self.process_request(socket_idx, &tx) {
                 log::error!("error handling request: {e}");
             }
         }
     }
 
-    fn default_response_request_builder_from(request: &DnsPacket) -> DnsPacketBuilder {
-        DnsPacketBuilder::default()
+    fn default_response_request_builder_from(&self, request: &DnsPacket) -> DnsPacketBuilder {
+        let mut builder = DnsPacketBuilder::default()
             .id(request.id())
             .recursion_desired(request.recursion_desired())
             .recursion_available(false)
-            .message_type(MessageType::Response)
-    }
+            .message_type(MessageType::Response);
 
-    fn cache_response_with_ttl(&self, question: &Question, response: &DnsPacket) {
-        if let Some(ttl) = response.min_ttl() {
-            self.cache.add(
-                question.name().clone(),
-                response.base().clone(),
-                CacheItemPolicy::AbsoluteExpiration(Duration::from_secs(ttl as u64)),
-            );
+        if let Some(edns) = self.response_edns(request) {
+            builder = builder.edns(edns);
         }
+
+        request
+            .questions()
+            .iter()
+            .fold(builder, |builder, question| {
+                builder.with_question(question.clone())
+            })
     }
 
-    fn cache_response_without_ttl(&self, question: &Question, response: &DnsPacket) {
-        self.cache.add(
-            question.name().clone(),
-            response.base().clone(),
-            CacheItemPolicy::NoExpiration,
-        );
+    /// An OPT record for the response if the request had one.
+    fn response_edns(&self, request: &DnsPacket) -> Option<Edns> {
+        request
+            .edns()
+            .map(|edns| Edns::new(self.max_udp_payload_size).with_dnssec_ok(edns.dnssec_ok()))
+    }
+
+    /// The client's advertised buffer size, capped by ours.
+    fn udp_payload_size(&self, request: &DnsPacket) -> u16 {
+        match request.edns() {
+            Some(edns) => edns.udp_payload_size().min(self.max_udp_payload_size),
+            None => MIN_UDP_PAYLOAD_SIZE,
+        }
     }
 }
@@ -0,0 +1,472 @@
+use crate::cache::{CacheItemPolicy, CacheOptions, CacheSize, MemoryCache};
+use crate::models::{
+    DnsPacket, DnsPacketBuilder, MessageType, Name, QueryClass, QueryType, Question, RawRecord,
+    RecordData, ResultCode, MAX_CNAME_CHAIN_LENGTH,
+};
+use crate::server::{Forwarder, ForwarderOptions};
+use anyhow::{anyhow, bail, Result};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+const DEFAULT_QUERY_PORT: u16 = 53;
+const DEFAULT_MAX_DEPTH: usize = 6;
+const DEFAULT_QUERY_BUDGET: usize = 64;
+const DEFAULT_TIMEOUT_MS: u64 = 1000;
+
+#[derive(Clone, Debug)]
+pub struct RecursorOptions {
+    pub root_hints: Vec<SocketAddr>,
+    /// Port of the servers learned from referrals.
+    pub query_port: u16,
+    /// How deep resolving name server addresses may nest.
+    pub max_depth: usize,
+    /// Queries a single client question may cost at most.
+    pub query_budget: usize,
+    pub timeout: Duration,
+}
+
+impl Default for RecursorOptions {
+    fn default() -> Self {
+        Self {
+            root_hints: Vec::new(),
+            query_port: DEFAULT_QUERY_PORT,
+            max_depth: DEFAULT_MAX_DEPTH,
+            query_budget: DEFAULT_QUERY_BUDGET,
+            timeout: Duration::from_millis(DEFAULT_TIMEOUT_MS),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct NameServer {
+    name: Name,
+    /// Empty for glueless name servers, resolved when needed.
+    addrs: Vec<SocketAddr>,
+}
+
+#[derive(Clone, Debug)]
+struct Delegation {
+    zone: Name,
+    servers: Vec<NameServer>,
+}
+
+impl CacheSize for Delegation {
+    fn cache_size(&self) -> usize {
+        let servers = self
+            .servers
+            .iter()
+            .map(|server| {
+                std::mem::size_of::<NameServer>()
+                    + server.name.wire_len()
+                    + server.addrs.len() * std::mem::size_of::<SocketAddr>()
+            })
+            .sum::<usize>();
+        std::mem::size_of::<Self>() + self.zone.wire_len() + servers
+    }
+}
+
+/// State of the resolution of one client question, shared by the nested
+/// resolutions of name server addresses.
+#[derive(Default)]
+struct Resolution {
+    queries: usize,
+    in_progress: Vec<(Name, QueryType)>,
+}
+
+struct Outcome {
+    result_code: ResultCode,
+    answers: Vec<RawRecord>,
+    /// The SOA record of a negative answer, for caching it.
+    authorities: Vec<RawRecord>,
+}
+
+/// Iterative resolver: walks down from the root hints following referrals,
+/// caching the delegations it learns on the way.
+pub struct Recursor {
+    forwarder: Forwarder,
+    options: RecursorOptions,
+    root: Arc<Delegation>,
+    delegations: MemoryCache<Name, Delegation>,
+}
+
+impl Recursor {
+    pub fn new(options: RecursorOptions, socket_pool_size: usize, cache: CacheOptions) -> Self {
+        let root = Delegation {
+            zone: Name::root(),
+            servers: options
+                .root_hints
+                .iter()
+                // the hints come without names
+                .map(|&addr| NameServer {
+                    name: Name::root(),
+                    addrs: vec![addr],
+                })
+                .collect(),
+        };
+
+        Self {
+            forwarder: Forwarder::new(ForwarderOptions {
+                timeout: options.timeout,
+                retries: 0,
+                socket_pool_size,
+                recursion_desired: false,
+            }),
+            options,
+            root: Arc::new(root),
+            delegations: MemoryCache::new(cache),
+        }
+    }
+
+    pub fn resolve(&self, question: &Question) -> Result<DnsPacket> {
+        let mut resolution = Resolution::default();
+        let outcome = self.resolve_name(
+            question.name(),
+            question.query_type(),
+            question.query_class(),
+            0,
+            &mut resolution,
+        )?;
+        log::debug!(
+            "resolved {} {} with {} queries",
+            question.name(),
+            question.query_type(),
+            resolution.queries
+        );
+
+        Ok(DnsPacketBuilder::default()
+            .message_type(MessageType::Response)
+            .result_code(outcome.result_code)
+            .with_question(question.clone())
+            .with_answers(outcome.answers)
+            .with_authorities(outcome.authorities)
+            .build())
+    }
+
+    fn resolve_name(
+        &self,
+        name: &Name,
+        query_type: QueryType,
+        query_class: QueryClass,
+        depth: usize,
+        resolution: &mut Resolution,
+    ) -> Result<Outcome> {
+        if depth > self.options.max_depth {
+            bail!("resolving {name} nested too deeply");
+        }
+
+        let key = (name.clone(), query_type);
+        if resolution.in_progress.contains(&key) {
+            bail!("resolution loop on {name} {query_type}");
+        }
+
+        resolution.in_progress.push(key);
+        let outcome = self.resolve_chain(name, query_type, query_class, depth, resolution);
+        resolution.in_progress.pop();
+
+        outcome
+    }
+
+    /// Follows CNAMEs to other zones, starting each link from its closest
+    /// known delegation.
+    fn resolve_chain(
+        &self,
+        name: &Name,
+        query_type: QueryType,
+        query_class: QueryClass,
+        depth: usize,
+        resolution: &mut Resolution,
+    ) -> Result<Outcome> {
+        let mut answers = Vec::new();
+        let mut visited = vec![name.clone()];
+
+        loop {
+            let name = visited.last().unwrap().clone();
+            let question = Question::new(name.clone(), query_type, query_class);
+            let (response, zone) = self.query_authoritative(&question, depth, resolution)?;
+
+            let unfinished =
+                Self::extract_answers(&response, &zone, query_type, &mut visited, &mut answers)?;
+            if !unfinished || response.result_code() != ResultCode::NoError {
+                return Ok(Outcome {
+                    result_code: response.result_code(),
+                    answers,
+                    authorities: response
+                        .authorities()
+                        .iter()
+                        .filter(|record| record.query_type() == QueryType::SOA)
+                        .cloned()
+                        .collect(),
+                });
+            }
+
+            log::debug!("following CNAME from {name} to {}", visited.last().unwrap());
+        }
+    }
+
+    /// Collects the answers for the last visited name, following the CNAMEs
+    /// present in the response. Only records within `zone`, the zone of the
+    /// answering servers, are accepted. Returns whether the chain goes on to
+    /// a name the response doesn't cover.
+    fn extract_answers(
+        response: &DnsPacket,
+        zone: &Name,
+        query_type: QueryType,
+        visited: &mut Vec<Name>,
+        answers: &mut Vec<RawRecord>,
+    ) -> Result<bool> {
+        let start = visited.len();
+
+        loop {
+            let current = visited.last().unwrap();
+            if !current.is_subdomain_of(zone) {
+                return Ok(true);
+            }
+
+            let owned = response
+                .answers()
+                .iter()
+                .filter(|record| record.name() == current);
+
+            let matching = owned
+                .clone()
+                .filter(|record| record.query_type() == query_type)
+                .cloned()
+                .collect::<Vec<_>>();
+            if !matching.is_empty() {
+                answers.extend(matching);
+                return Ok(false);
+            }
+
+            let Some(cname) = owned
+                .clone()
+                .find(|record| record.query_type() == QueryType::CNAME)
+            else {
+                return Ok(visited.len() > start);
+            };
+            let RecordData::CNAME(target) = cname.data() else {
+                bail!("malformed CNAME record for {current}");
+            };
+
+            let target = target.clone();
+            if visited.contains(&target) {
+                bail!("CNAME loop at {target}");
+            }
+            if visited.len() > MAX_CNAME_CHAIN_LENGTH {
+                bail!("CNAME chain for {} too long", visited[0]);
+            }
+
+            answers.push(cname.clone());
+            visited.push(target);
+        }
+    }
+
+    /// Asks the servers of the closest known delegation and follows their
+    /// referrals until a server answers authoritatively. Returns the answer
+    /// along with the zone it came from.
+    fn query_authoritative(
+        &self,
+        question: &Question,
+        depth: usize,
+        resolution: &mut Resolution,
+    ) -> Result<(DnsPacket, Name)> {
+        let mut delegation = self.closest_delegation(question.name());
+
+        loop {
+            let response = self.query_delegation(&delegation, question, depth, resolution)?;
+
+            match self.referral(&response, &delegation.zone) {
+                Some((next, ttl)) => {
+                    log::debug!(
+                        "referral for {} from '{}' to '{}'",
+                        question.name(),
+                        delegation.zone,
+                        next.zone
+                    );
+                    if ttl > 0 {
+                        self.delegations.add(
+                            next.zone.clone(),
+                            next.clone(),
+                            CacheItemPolicy::AbsoluteExpiration(Duration::from_secs(ttl.into())),
+                        );
+                    }
+                    delegation = Arc::new(next);
+                }
+                None => return Ok((response, delegation.zone.clone())),
+            }
+        }
+    }
+
+    fn query_delegation(
+        &self,
+        delegation: &Delegation,
+        question: &Question,
+        depth: usize,
+        resolution: &mut Resolution,
+    ) -> Result<DnsPacket> {
+        let mut last_error = None;
+
+        for server in delegation.servers.iter().filter(|s| !s.addrs.is_empty()) {
+            for &addr in &server.addrs {
+                match self.query_server(addr, question, &delegation.zone, resolution)? {
+                    Ok(response) => return Ok(response),
+                    Err(e) => last_error = Some(e),
+                }
+            }
+        }
+
+        // glueless name servers cost a resolution of their own, so they're
+        // only tried when none of the others answered
+        for server in delegation.servers.iter().filter(|s| s.addrs.is_empty()) {
+            let addrs = match self.resolve_addresses(&server.name, depth + 1, resolution) {
+                Ok(addrs) => addrs,
+                Err(e) => {
+                    log::debug!("failed resolving name server {}: {e}", server.name);
+                    last_error = Some(e);
+                    continue;
+                }
+            };
+
+            for addr in addrs {
+                match self.query_server(addr, question, &delegation.zone, resolution)? {
+                    Ok(response) => return Ok(response),
+                    Err(e) => last_error = Some(e),
+                }
+            }
+        }
+
+        Err(last_error
+            .unwrap_or_else(|| anyhow!("no name servers for '{}'", delegation.zone))
+            .context(format!("no server for '{}' answered", delegation.zone)))
+    }
+
+    /// The outer error aborts the whole resolution, the inner one only means
+    /// this server didn't give a usable answer. Referrals have to lead
+    /// strictly closer to the name, from the servers of `zone` down to it,
+    /// anything else makes the server lame. That also rules out referral
+    /// loops.
+    fn query_server(
+        &self,
+        addr: SocketAddr,
+        question: &Question,
+        zone: &Name,
+        resolution: &mut Resolution,
+    ) -> Result<Result<DnsPacket>> {
+        if resolution.queries >= self.options.query_budget {
+            bail!("query budget of {} exhausted", self.options.query_budget);
+        }
+        resolution.queries += 1;
+
+        let response = match self.forwarder.query(addr, question) {
+            Ok(response) => response,
+            Err(e) => return Ok(Err(e)),
+        };
+
+        if let Some(cut) = Self::referral_cut(&response) {
+            if cut == zone || !cut.is_subdomain_of(zone) || !question.name().is_subdomain_of(cut) {
+                return Ok(Err(anyhow!(
+                    "{addr} is lame for '{zone}', it referred {} to '{cut}'",
+                    question.name()
+                )));
+            }
+        }
+
+        match response.result_code() {
+            ResultCode::NoError | ResultCode::NameError => Ok(Ok(response)),
+            result_code => Ok(Err(anyhow!("{addr} answered {result_code:?}"))),
+        }
+    }
+
+    fn resolve_addresses(
+        &self,
+        name: &Name,
+        depth: usize,
+        resolution: &mut Resolution,
+    ) -> Result<Vec<SocketAddr>> {
+        let outcome = self.resolve_name(name, QueryType::A, QueryClass::IN, depth, resolution)?;
+
+        let addrs = outcome
+            .answers
+            .iter()
+            .filter_map(|record| match record.data() {
+                RecordData::A(ip) => Some(SocketAddr::from((*ip, self.options.query_port))),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        if addrs.is_empty() {
+            bail!("no address for name server {name}");
+        }
+
+        Ok(addrs)
+    }
+
+    /// The zone a response refers to, unless it's an answer.
+    fn referral_cut(response: &DnsPacket) -> Option<&Name> {
+        if response.result_code() != ResultCode::NoError
+            || !response.answers().is_empty()
+            || response
+                .authorities()
+                .iter()
+                .any(|record| record.query_type() == QueryType::SOA)
+        {
+            return None;
+        }
+
+        response
+            .authorities()
+            .iter()
+            .find(|record| record.query_type() == QueryType::NS)
+            .map(|record| record.name())
+    }
+
+    /// Extracts the delegation from a referral response of the servers of
+    /// `zone`, along with how long it may be cached.
+    fn referral(&self, response: &DnsPacket, zone: &Name) -> Option<(Delegation, u32)> {
+        let cut = Self::referral_cut(response)?.clone();
+
+        let mut ttl = u32::MAX;
+        let mut servers = Vec::new();
+        for record in response.authorities() {
+            let RecordData::NS(server) = record.data() else {
+                continue;
+            };
+            if *record.name() != cut {
+                continue;
+            }
+            ttl = ttl.min(record.ttl());
+
+            // glue is only trusted from the servers of a parent zone
+            let addrs = if server.is_subdomain_of(zone) {
+                response
+                    .additional()
+                    .iter()
+                    .filter(|glue| glue.name() == server)
+                    .filter_map(|glue| match glue.data() {
+                        RecordData::A(ip) => Some(SocketAddr::from((*ip, self.options.query_port))),
+                        RecordData::AAAA(ip) => {
+                            Some(SocketAddr::from((*ip, self.options.query_port)))
+                        }
+                        _ => None,
+                    })
+                    .collect()
+            } else {
+                Vec::new()
+            };
+
+            servers.push(NameServer {
+                name: server.clone(),
+                addrs,
+            });
+        }
+
+        Some((Delegation { zone: cut, servers }, ttl))
+    }
+
+    fn closest_delegation(&self, name: &Name) -> Arc<Delegation> {
+        name.ancestors()
+            .take_while(|zone| !zone.is_root())
+            .find_map(|zone| self.delegations.get(&zone))
+            .unwrap_or_else(|| Arc::clone(&self.root))
+    }
+}
@@ -0,0 +1,179 @@
+use crate::models::DnsPacket;
+use anyhow::{bail, Result};
+use rustc_hash::FxHashMap;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const MAX_MESSAGE_SIZE: usize = u16::MAX as usize;
+
+const DEFAULT_IDLE_TIMEOUT_SECS: u64 = 10;
+const DEFAULT_MAX_CONNECTIONS_PER_CLIENT: usize = 8;
+
+/// Responses are written under the connection's lock, so a client that stops
+/// reading holds up the workers answering it for this long at most.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Debug)]
+pub struct TcpOptions {
+    /// Connections without a query for this long are closed, unless they
+    /// still wait for a response.
+    pub idle_timeout: Duration,
+    pub max_connections_per_client: usize,
+}
+
+impl Default for TcpOptions {
+    fn default() -> Self {
+        Self {
+            idle_timeout: Duration::from_secs(DEFAULT_IDLE_TIMEOUT_SECS),
+            max_connections_per_client: DEFAULT_MAX_CONNECTIONS_PER_CLIENT,
+        }
+    }
+}
+
+/// A client connection carrying messages with the two-byte length prefix of
+/// RFC 1035 4.2.2. Queries can be pipelined: the reader keeps going while
+/// workers write the responses in whatever order they finish.
+pub struct TcpConnection {
+    peer: SocketAddr,
+    writer: Mutex<TcpStream>,
+    pending: AtomicUsize,
+}
+
+impl TcpConnection {
+    pub fn new(stream: &TcpStream, options: &TcpOptions) -> Result<Self> {
+        stream.set_read_timeout(Some(options.idle_timeout))?;
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        stream.set_nodelay(true)?;
+
+        Ok(Self {
+            peer: stream.peer_addr()?,
+            writer: Mutex::new(stream.try_clone()?),
+            pending: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn peer(&self) -> SocketAddr {
+        self.peer
+    }
+
+    /// Returns `Ok(None)` once the client closed the connection or stayed
+    /// idle with no response pending.
+    pub fn read_message(&self, reader: &mut TcpStream) -> Result<Option<Vec<u8>>> {
+        let mut len = [0u8; 2];
+        if !self.read_exact(reader, &mut len, true)? {
+            return Ok(None);
+        }
+
+        let mut message = vec![0u8; u16::from_be_bytes(len) as usize];
+        if !self.read_exact(reader, &mut message, false)? {
+            bail!("connection closed in the middle of a message");
+        }
+
+        self.pending.fetch_add(1, Ordering::SeqCst);
+
+        Ok(Some(message))
+    }
+
+    pub fn send(&self, response: &DnsPacket) -> Result<()> {
+        self.pending.fetch_sub(1, Ordering::SeqCst);
+
+        let mut buf = vec![0u8; MAX_MESSAGE_SIZE + 2];
+        let len = response.to_bytes(&mut buf[2..])?;
+        buf[..2].copy_from_slice(&(len as u16).to_be_bytes());
+
+        // a single write keeps concurrent responses from interleaving
+        let mut writer = self.writer.lock().unwrap();
+        if let Err(e) = writer.write_all(&buf[..len + 2]) {
+            // whatever part got written leaves the stream out of step, and
+            // the responses still queued would only wait out the timeout too
+            let _ = writer.shutdown(Shutdown::Both);
+            return Err(e.into());
+        }
+
+        Ok(())
+    }
+
+    /// Drops a query which won't be answered, e.g. a malformed one.
+    pub fn discard(&self) {
+        self.pending.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    fn read_exact(
+        &self,
+        reader: &mut TcpStream,
+        buf: &mut [u8],
+        message_start: bool,
+    ) -> Result<bool> {
+        let mut filled = 0;
+
+        while filled < buf.len() {
+            match reader.read(&mut buf[filled..]) {
+                Ok(0) => return Ok(false),
+                Ok(n) => filled += n,
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    if message_start && filled == 0 && self.pending.load(Ordering::SeqCst) > 0 {
+                        continue;
+                    }
+                    log::debug!("closing idle connection from {}", self.peer);
+                    return Ok(false);
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+/// Counts open connections per client address.
+pub struct ConnectionLimiter {
+    max_per_client: usize,
+    connections: Mutex<FxHashMap<IpAddr, usize>>,
+}
+
+/// Releases its connection slot when dropped.
+pub struct ConnectionSlot {
+    limiter: Arc<ConnectionLimiter>,
+    ip: IpAddr,
+}
+
+impl ConnectionLimiter {
+    pub fn new(max_per_client: usize) -> Self {
+        Self {
+            max_per_client,
+            connections: Mutex::default(),
+        }
+    }
+
+    pub fn acquire(self: &Arc<Self>, ip: IpAddr) -> Option<ConnectionSlot> {
+        let mut connections = self.connections.lock().unwrap();
+
+        let count = connections.entry(ip).or_default();
+        if *count >= self.max_per_client {
+            return None;
+        }
+        *count += 1;
+
+        Some(ConnectionSlot {
+            limiter: Arc::clone(self),
+            ip,
+        })
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        let mut connections = self.limiter.connections.lock().unwrap();
+
+        if let Some(count) = connections.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                connections.remove(&self.ip);
+            }
+        }
+    }
+}
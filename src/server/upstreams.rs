@@ -0,0 +1,206 @@
+use crate::models::{DnsPacket, Question, ResultCode};
+use crate::server::{Forwarder, ForwarderOptions};
+use anyhow::{anyhow, Result};
+use std::fmt::{Display, Formatter};
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const DEFAULT_MAX_FAILURES: u32 = 3;
+const DEFAULT_COOLDOWN_SECS: u64 = 30;
+
+/// Weight of the previous value in the smoothed RTT, as in RFC 6298.
+const SRTT_DECAY: f64 = 0.875;
+
+#[derive(Clone, Debug)]
+pub struct HealthOptions {
+    /// Consecutive failures after which a server is taken out of rotation.
+    pub max_failures: u32,
+    /// How long a server stays out of rotation.
+    pub cooldown: Duration,
+}
+
+impl Default for HealthOptions {
+    fn default() -> Self {
+        Self {
+            max_failures: DEFAULT_MAX_FAILURES,
+            cooldown: Duration::from_secs(DEFAULT_COOLDOWN_SECS),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct UpstreamStats {
+    pub server: SocketAddr,
+    pub srtt: Option<Duration>,
+    pub queries: u64,
+    pub failures: u64,
+    pub healthy: bool,
+}
+
+impl Display for UpstreamStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} queries, {} failures, srtt {}, {}",
+            self.server,
+            self.queries,
+            self.failures,
+            match self.srtt {
+                Some(srtt) => format!("{}ms", srtt.as_millis()),
+                None => "unknown".to_string(),
+            },
+            if self.healthy { "healthy" } else { "down" }
+        )
+    }
+}
+
+#[derive(Default)]
+struct UpstreamState {
+    srtt: Option<Duration>,
+    queries: u64,
+    failures: u64,
+    consecutive_failures: u32,
+    down_until: Option<Instant>,
+}
+
+struct Upstream {
+    server: SocketAddr,
+    state: Mutex<UpstreamState>,
+}
+
+/// A list of interchangeable upstream servers. Queries go to the healthy
+/// server with the lowest smoothed RTT and fail over to the next one when it
+/// times out or answers SERVFAIL.
+pub struct UpstreamGroup {
+    forwarder: Forwarder,
+    options: HealthOptions,
+    upstreams: Vec<Upstream>,
+}
+
+impl UpstreamGroup {
+    pub fn new(
+        servers: &[SocketAddr],
+        forwarder_options: ForwarderOptions,
+        options: HealthOptions,
+    ) -> Self {
+        Self {
+            forwarder: Forwarder::new(forwarder_options),
+            options,
+            upstreams: servers
+                .iter()
+                .map(|&server| Upstream {
+                    server,
+                    state: Mutex::default(),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn query(&self, question: &Question) -> Result<DnsPacket> {
+        let mut last_error = None;
+        let mut server_failure = None;
+
+        for idx in self.preference_order() {
+            let upstream = &self.upstreams[idx];
+
+            let start = Instant::now();
+            match self.forwarder.query(upstream.server, question) {
+                Ok(response) if response.result_code() == ResultCode::ServerFailure => {
+                    log::warn!("upstream {} answered SERVFAIL", upstream.server);
+                    self.record_failure(upstream);
+                    server_failure = Some(response);
+                }
+                Ok(response) => {
+                    self.record_success(upstream, start.elapsed());
+                    return Ok(response);
+                }
+                Err(e) => {
+                    log::warn!("upstream {} failed: {e}", upstream.server);
+                    self.record_failure(upstream);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        match server_failure {
+            Some(response) => Ok(response),
+            None => Err(last_error.unwrap_or_else(|| anyhow!("no upstream servers configured"))),
+        }
+    }
+
+    pub fn stats(&self) -> Vec<UpstreamStats> {
+        let now = Instant::now();
+
+        self.upstreams
+            .iter()
+            .map(|upstream| {
+                let state = upstream.state.lock().unwrap();
+                UpstreamStats {
+                    server: upstream.server,
+                    srtt: state.srtt,
+                    queries: state.queries,
+                    failures: state.failures,
+                    healthy: !Self::is_down(&state, now),
+                }
+            })
+            .collect()
+    }
+
+    /// Healthy servers by smoothed RTT, servers without one first so they get
+    /// measured, then the ones in cooldown as the last resort.
+    fn preference_order(&self) -> Vec<usize> {
+        let now = Instant::now();
+
+        let mut order = self
+            .upstreams
+            .iter()
+            .enumerate()
+            .map(|(idx, upstream)| {
+                let state = upstream.state.lock().unwrap();
+                (
+                    Self::is_down(&state, now),
+                    state.srtt.unwrap_or_default(),
+                    idx,
+                )
+            })
+            .collect::<Vec<_>>();
+        order.sort();
+
+        order.into_iter().map(|(_, _, idx)| idx).collect()
+    }
+
+    fn is_down(state: &UpstreamState, now: Instant) -> bool {
+        state.down_until.is_some_and(|down_until| down_until > now)
+    }
+
+    fn record_success(&self, upstream: &Upstream, rtt: Duration) {
+        let mut state = upstream.state.lock().unwrap();
+
+        state.queries += 1;
+        state.consecutive_failures = 0;
+        state.down_until = None;
+        state.srtt = Some(match state.srtt {
+            Some(srtt) => srtt.mul_f64(SRTT_DECAY) + rtt.mul_f64(1.0 - SRTT_DECAY),
+            None => rtt,
+        });
+    }
+
+    fn record_failure(&self, upstream: &Upstream) {
+        let mut state = upstream.state.lock().unwrap();
+
+        state.queries += 1;
+        state.failures += 1;
+        state.consecutive_failures += 1;
+
+        if state.consecutive_failures >= self.options.max_failures {
+            log::warn!(
+                "taking upstream {} out of rotation for {}s",
+                upstream.server,
+                self.options.cooldown.as_secs()
+            );
+            state.consecutive_failures = 0;
+            state.down_until = Some(Instant::now() + self.options.cooldown);
+        }
+    }
+}
@@ -1,17 +1,30 @@
+use crate::models::Name;
 use anyhow::{anyhow, bail, Result};
+use rustc_hash::FxHashMap;
 
 pub struct SmartBuffer<T: AsRef<[u8]>> {
     buf: T,
     pos: usize,
+    /// Offsets of the names written so far and of all their suffixes, for
+    /// compression pointers. Lookups ignore case like `Name` equality does.
+    names: FxHashMap<Name, usize>,
 }
 
 const BUFFER_OVERFLOW_ERROR_MSG: &str = "buffer overflow";
 const POSSIBLY_LOOP_IN_QUESTION_ERROR_MSG: &str = "possibly loop in the question";
 const WRONG_LABEL_LENGTH: &str = "length of label is greater than 63 bytes";
+const WRONG_NAME_LENGTH: &str = "length of name is greater than 255 bytes";
+
+/// Pointers have 14 bits for the offset.
+const MAX_POINTER_OFFSET: usize = 0x3FFF;
 
 impl<T: AsRef<[u8]>> SmartBuffer<T> {
     pub fn new(buf: T) -> Self {
-        Self { buf, pos: 0 }
+        Self {
+            buf,
+            pos: 0,
+            names: FxHashMap::default(),
+        }
     }
 
     fn get(&mut self, pos: usize) -> Result<u8> {
@@ -23,7 +36,7 @@ impl<T: AsRef<[u8]>> SmartBuffer<T> {
     }
 
     fn get_slice(&mut self, start: usize, len: usize) -> Result<&[u8]> {
-        if start + len >= self.buf.as_ref().len() {
+        if start + len > self.buf.as_ref().len() {
             bail!(BUFFER_OVERFLOW_ERROR_MSG);
         }
 
@@ -40,8 +53,8 @@ impl<T: AsRef<[u8]>> SmartBuffer<T> {
         Ok(byte)
     }
 
-    fn seek(&mut self, pos: usize) -> Result<()> {
-        if pos >= self.buf.as_ref().len() {
+    pub fn seek(&mut self, pos: usize) -> Result<()> {
+        if pos > self.buf.as_ref().len() {
             bail!(BUFFER_OVERFLOW_ERROR_MSG);
         }
 
@@ -50,6 +63,18 @@ impl<T: AsRef<[u8]>> SmartBuffer<T> {
         Ok(())
     }
 
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buf.as_ref().len()
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8> {
+        self.next_byte()
+    }
+
     pub fn read_u16(&mut self) -> Result<u16> {
         Ok(((self.next_byte()? as u16) << 8) | (self.next_byte()? as u16))
     }
@@ -72,8 +97,9 @@ impl<T: AsRef<[u8]>> SmartBuffer<T> {
         Ok(res)
     }
 
-    pub fn read_qname(&mut self) -> Result<String> {
-        let mut result = String::with_capacity(16);
+    pub fn read_qname(&mut self) -> Result<Name> {
+        let mut labels = Vec::with_capacity(4);
+        let mut wire_len = 1;
 
         let mut pos = self.pos;
 
@@ -102,15 +128,16 @@ impl<T: AsRef<[u8]>> SmartBuffer<T> {
                 if len == 0 {
                     break;
                 }
+                if len > 63 {
+                    bail!(WRONG_LABEL_LENGTH);
+                }
 
-                if !result.is_empty() {
-                    // delimiter
-                    result.push('.');
+                wire_len += 1 + len as usize;
+                if wire_len > 255 {
+                    bail!(WRONG_NAME_LENGTH);
                 }
 
-                result.push_str(
-                    &String::from_utf8_lossy(self.get_slice(pos, len as usize)?).to_lowercase(),
-                );
+                labels.push(self.get_slice(pos, len as usize)?.to_vec());
 
                 pos += len as usize;
             }
@@ -120,7 +147,7 @@ impl<T: AsRef<[u8]>> SmartBuffer<T> {
             self.seek(pos)?;
         }
 
-        Ok(result)
+        Name::from_labels(labels.iter().map(Vec::as_slice))
     }
 }
 
@@ -158,6 +185,19 @@ impl<T: AsMut<[u8]> + AsRef<[u8]>> SmartBuffer<T> {
         Ok(())
     }
 
+    /// Overwrites two already written bytes, e.g. a length known only after
+    /// the data it describes has been written.
+    pub fn set_u16(&mut self, pos: usize, val: u16) -> Result<()> {
+        if pos + 2 > self.pos {
+            bail!(BUFFER_OVERFLOW_ERROR_MSG)
+        }
+
+        self.buf.as_mut()[pos] = (val >> 8) as u8;
+        self.buf.as_mut()[pos + 1] = (val & 0xFF) as u8;
+
+        Ok(())
+    }
+
     pub fn write_slice<S: AsRef<[u8]>>(&mut self, slice: S) -> Result<()> {
         for byte in slice.as_ref().iter() {
             self.write_byte(*byte)?;
@@ -166,15 +206,44 @@ impl<T: AsMut<[u8]> + AsRef<[u8]>> SmartBuffer<T> {
         Ok(())
     }
 
-    pub fn write_qname<S: AsRef<str>>(&mut self, qname: S) -> Result<()> {
-        for label in qname.as_ref().split('.') {
-            if label.len() > 63 {
-                bail!(WRONG_LABEL_LENGTH)
+    /// Moves back to `pos`, forgetting whatever was written after it.
+    pub fn truncate(&mut self, pos: usize) -> Result<()> {
+        self.seek(pos)?;
+        self.names.retain(|_, offset| *offset < pos);
+
+        Ok(())
+    }
+
+    /// Writes the name, replacing its longest suffix already present in the
+    /// message with a compression pointer.
+    pub fn write_qname(&mut self, qname: &Name) -> Result<()> {
+        self.write_name(qname, true)
+    }
+
+    /// For names in RDATA of types defined after RFC 1035, which receivers
+    /// may not know how to decompress (RFC 3597 4).
+    pub fn write_qname_uncompressed(&mut self, qname: &Name) -> Result<()> {
+        self.write_name(qname, false)
+    }
+
+    fn write_name(&mut self, name: &Name, compress: bool) -> Result<()> {
+        for suffix in name.ancestors() {
+            let Some(label) = suffix.first_label() else {
+                break;
+            };
+
+            if compress {
+                if let Some(&offset) = self.names.get(&suffix) {
+                    return self.write_u16(0xC000 | offset as u16);
+                }
             }
 
+            let pos = self.pos;
             self.write_u8(label.len() as u8)?;
-            for byte in label.as_bytes() {
-                self.write_u8(*byte)?;
+            self.write_slice(label)?;
+
+            if pos <= MAX_POINTER_OFFSET {
+                self.names.entry(suffix).or_insert(pos);
             }
         }
 
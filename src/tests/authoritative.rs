@@ -0,0 +1,103 @@
+use crate::config::Config;
+use crate::models::{DnsPacket, QueryType, ResultCode};
+use crate::tests::util::{query, request, spawn_upstream, start_server, Reply};
+use std::net::UdpSocket;
+use std::time::Duration;
+
+fn is_authoritative(response: &[u8]) -> bool {
+    response[2] & 0b100 != 0
+}
+
+#[test]
+fn answers_for_its_zones_without_forwarding() {
+    let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let mut config = Config::default();
+    config.upstream.servers = vec![upstream.local_addr().unwrap()];
+    let addr = start_server(config);
+
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(1)))
+        .unwrap();
+
+    // bind.txt is the default zone
+    let response = query(
+        &client,
+        addr,
+        &request("www.example.com", QueryType::AAAA).build(),
+    );
+    assert!(is_authoritative(&response));
+    let packet = DnsPacket::from_bytes(&response).unwrap();
+    assert_eq!(packet.answers().len(), 1);
+
+    let response = query(
+        &client,
+        addr,
+        &request("www.example.com", QueryType::MX).build(),
+    );
+    assert!(is_authoritative(&response));
+    let packet = DnsPacket::from_bytes(&response).unwrap();
+    assert_eq!(packet.result_code(), ResultCode::NoError);
+    assert!(packet.answers().is_empty());
+    assert_eq!(packet.authorities()[0].query_type(), QueryType::SOA);
+
+    let response = query(
+        &client,
+        addr,
+        &request("example.com", QueryType::MX).build(),
+    );
+    let packet = DnsPacket::from_bytes(&response).unwrap();
+    assert_eq!(
+        packet.additional()[0].to_string(),
+        "mail.example.com. 300 IN A 192.168.254.4"
+    );
+
+    let response = query(
+        &client,
+        addr,
+        &request("missing.example.com", QueryType::A).build(),
+    );
+    assert!(is_authoritative(&response));
+    let packet = DnsPacket::from_bytes(&response).unwrap();
+    assert_eq!(packet.result_code(), ResultCode::NameError);
+    assert_eq!(packet.authorities()[0].query_type(), QueryType::SOA);
+
+    upstream
+        .set_read_timeout(Some(Duration::from_millis(100)))
+        .unwrap();
+    assert!(upstream.recv(&mut [0; 512]).is_err());
+}
+
+#[test]
+fn resolves_cnames_leaving_its_zones_upstream() {
+    let mut config = Config::default();
+    config.upstream.servers = vec![spawn_upstream(|| Reply::Answer(ResultCode::NoError, 15))];
+    let addr = start_server(config);
+
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(1)))
+        .unwrap();
+
+    // ftp.example.com is a CNAME to ftp.example.net in bind.txt
+    let response = query(
+        &client,
+        addr,
+        &request("ftp.example.com", QueryType::A).build(),
+    );
+    assert!(is_authoritative(&response));
+    let packet = DnsPacket::from_bytes(&response).unwrap();
+    assert_eq!(packet.result_code(), ResultCode::NoError);
+    let answers = packet
+        .answers()
+        .iter()
+        .map(|record| record.to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        answers,
+        [
+            "ftp.example.com. 300 IN CNAME ftp.example.net.",
+            "ftp.example.net. 15 IN A 192.0.2.1"
+        ]
+    );
+}
@@ -0,0 +1,496 @@
+use crate::cache::{CacheOptions, DnsCache, DnsCacheBase};
+use crate::helpers::UnixTimeProvider;
+use crate::models::{
+    DnsPacket, DnsPacketBuilder, MessageType, QueryClass, QueryType, Question, RawRecord,
+    RawRecordType, RecordData, ResultCode, MAX_CNAME_CHAIN_LENGTH,
+};
+use crate::tests::util::question;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+fn records(records: Vec<(&str, RecordData)>) -> Vec<RawRecord> {
+    let mut builder = DnsPacketBuilder::default();
+    for (name, data) in records {
+        builder = builder
+            .new_raw_record()
+            .name(name)
+            .ttl(300)
+            .data(data)
+            .add_raw_record(RawRecordType::Answer)
+            .unwrap();
+    }
+    builder.build().answers().to_vec()
+}
+
+fn lookup(cache: &DnsCache, name: &str, query_type: QueryType) -> Option<Vec<String>> {
+    cache
+        .lookup(&question(name, query_type))
+        .map(|cached| cached.answers.iter().map(|r| r.to_string()).collect())
+}
+
+#[test]
+fn keys_by_name_type_and_class() {
+    let cache = DnsCache::new(CacheOptions::default());
+    cache.insert_rrsets(&records(vec![(
+        "example.com",
+        RecordData::A(Ipv4Addr::new(192, 0, 2, 1)),
+    )]));
+
+    assert_eq!(
+        lookup(&cache, "EXAMPLE.com", QueryType::A).unwrap(),
+        vec!["example.com. 300 IN A 192.0.2.1"]
+    );
+    assert!(lookup(&cache, "example.com", QueryType::AAAA).is_none());
+    assert!(lookup(&cache, "example.com", QueryType::MX).is_none());
+    assert!(cache
+        .lookup(&Question::new(
+            "example.com".parse().unwrap(),
+            QueryType::A,
+            QueryClass::CH
+        ))
+        .is_none());
+}
+
+#[test]
+fn reuses_cname_across_types() {
+    let cache = DnsCache::new(CacheOptions::default());
+    cache.insert_rrsets(&records(vec![
+        (
+            "www.example.com",
+            RecordData::CNAME("cdn.example.net".parse().unwrap()),
+        ),
+        ("cdn.example.net", RecordData::AAAA(Ipv6Addr::LOCALHOST)),
+    ]));
+
+    assert_eq!(
+        lookup(&cache, "www.example.com", QueryType::AAAA).unwrap(),
+        vec![
+            "www.example.com. 300 IN CNAME cdn.example.net.",
+            "cdn.example.net. 300 IN AAAA ::1"
+        ]
+    );
+    assert!(lookup(&cache, "www.example.com", QueryType::A).is_none());
+
+    cache.insert_rrsets(&records(vec![(
+        "cdn.example.net",
+        RecordData::A(Ipv4Addr::LOCALHOST),
+    )]));
+    assert_eq!(
+        lookup(&cache, "www.example.com", QueryType::A).unwrap(),
+        vec![
+            "www.example.com. 300 IN CNAME cdn.example.net.",
+            "cdn.example.net. 300 IN A 127.0.0.1"
+        ]
+    );
+    assert_eq!(
+        lookup(&cache, "www.example.com", QueryType::CNAME)
+            .unwrap()
+            .len(),
+        1
+    );
+}
+
+#[test]
+fn limits_cname_chains() {
+    let cache = DnsCache::new(CacheOptions::default());
+    let mut chain = (0..=MAX_CNAME_CHAIN_LENGTH)
+        .map(|i| {
+            let target = format!("c{}.example.com", i + 1);
+            (
+                format!("c{i}.example.com"),
+                RecordData::CNAME(target.parse().unwrap()),
+            )
+        })
+        .collect::<Vec<_>>();
+    chain.push((
+        format!("c{}.example.com", MAX_CNAME_CHAIN_LENGTH + 1),
+        RecordData::A(Ipv4Addr::LOCALHOST),
+    ));
+    cache.insert_rrsets(&records(
+        chain
+            .iter()
+            .map(|(name, data)| (name.as_str(), data.clone()))
+            .collect(),
+    ));
+
+    let answers = lookup(&cache, "c1.example.com", QueryType::A).unwrap();
+    assert_eq!(answers.len(), MAX_CNAME_CHAIN_LENGTH + 1);
+    assert!(lookup(&cache, "c0.example.com", QueryType::A).is_none());
+}
+
+fn response(
+    question: &Question,
+    result_code: ResultCode,
+    answers: Vec<RawRecord>,
+    soa_ttl: Option<u32>,
+) -> DnsPacket {
+    let mut builder = DnsPacketBuilder::default()
+        .message_type(MessageType::Response)
+        .result_code(result_code)
+        .with_question(question.clone())
+        .with_answers(answers);
+    if let Some(ttl) = soa_ttl {
+        builder = builder
+            .new_raw_record()
+            .name("example.com")
+            .ttl(ttl)
+            .data(RecordData::SOA {
+                mname: "ns1.example.com".parse().unwrap(),
+                rname: "hostmaster.example.com".parse().unwrap(),
+                serial: 1,
+                refresh: 3600,
+                retry: 600,
+                expire: 86400,
+                minimum: 300,
+            })
+            .add_raw_record(RawRecordType::Authority)
+            .unwrap();
+    }
+    builder.build()
+}
+
+#[test]
+fn caches_nxdomain_for_every_type() {
+    let cache = DnsCache::new(CacheOptions::default());
+    let missing = question("missing.example.com", QueryType::A);
+    cache.insert_response(
+        &missing,
+        &response(&missing, ResultCode::NameError, Vec::new(), Some(3600)),
+    );
+
+    for query_type in [QueryType::A, QueryType::MX] {
+        let cached = cache
+            .lookup(&question("MISSING.example.com", query_type))
+            .unwrap();
+        assert_eq!(cached.result_code, ResultCode::NameError);
+        assert!(cached.answers.is_empty());
+        // capped by the SOA minimum
+        assert_eq!(cached.authorities[0].ttl(), 300);
+    }
+    assert!(lookup(&cache, "example.com", QueryType::A).is_none());
+}
+
+#[test]
+fn caches_nodata_per_type() {
+    let cache = DnsCache::new(CacheOptions::default());
+    let aaaa = question("example.com", QueryType::AAAA);
+    cache.insert_response(
+        &aaaa,
+        &response(&aaaa, ResultCode::NoError, Vec::new(), Some(60)),
+    );
+
+    let cached = cache.lookup(&aaaa).unwrap();
+    assert_eq!(cached.result_code, ResultCode::NoError);
+    assert!(cached.answers.is_empty());
+    assert_eq!(cached.authorities[0].ttl(), 60);
+    assert!(lookup(&cache, "example.com", QueryType::A).is_none());
+
+    // nothing tells how long a negative answer without SOA holds
+    let mx = question("example.com", QueryType::MX);
+    cache.insert_response(&mx, &response(&mx, ResultCode::NoError, Vec::new(), None));
+    assert!(cache.lookup(&mx).is_none());
+}
+
+#[test]
+fn caches_cnames_leading_to_nxdomain() {
+    let cache = DnsCache::new(CacheOptions::default());
+    let www = question("www.example.com", QueryType::A);
+    let cname = records(vec![(
+        "www.example.com",
+        RecordData::CNAME("gone.example.com".parse().unwrap()),
+    )]);
+    cache.insert_response(
+        &www,
+        &response(&www, ResultCode::NameError, cname, Some(300)),
+    );
+
+    let cached = cache.lookup(&www).unwrap();
+    assert_eq!(cached.result_code, ResultCode::NameError);
+    assert_eq!(cached.answers.len(), 1);
+    assert_eq!(
+        cache
+            .lookup(&question("gone.example.com", QueryType::TXT))
+            .unwrap()
+            .result_code,
+        ResultCode::NameError
+    );
+}
+
+#[test]
+fn caches_only_the_answers_on_the_question_chain() {
+    let cache = DnsCache::new(CacheOptions::default());
+    let www = question("www.example.com", QueryType::A);
+    let answers = records(vec![
+        (
+            "www.example.com",
+            RecordData::CNAME("web.example.com".parse().unwrap()),
+        ),
+        (
+            "web.example.com",
+            RecordData::A(Ipv4Addr::new(192, 0, 2, 1)),
+        ),
+        ("bank.example.net", RecordData::A(Ipv4Addr::new(6, 6, 6, 6))),
+    ]);
+    cache.insert_response(&www, &response(&www, ResultCode::NoError, answers, None));
+
+    assert_eq!(cache.lookup(&www).unwrap().answers.len(), 2);
+    assert!(lookup(&cache, "web.example.com", QueryType::A).is_some());
+    assert!(lookup(&cache, "bank.example.net", QueryType::A).is_none());
+}
+
+#[derive(Clone, Default)]
+struct FakeClock(Arc<AtomicU64>);
+
+impl UnixTimeProvider for FakeClock {
+    fn unix_time_as_secs(&self) -> u64 {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+#[test]
+fn counts_ttls_down() {
+    let clock = FakeClock::default();
+    let cache = DnsCacheBase::with_clock(clock.clone(), CacheOptions::default());
+
+    let mut records = records(vec![
+        ("example.com", RecordData::A(Ipv4Addr::new(192, 0, 2, 1))),
+        ("example.com", RecordData::A(Ipv4Addr::new(192, 0, 2, 2))),
+    ]);
+    records[1] = records[1].clone().with_ttl(100);
+    cache.insert_rrsets(&records);
+
+    let a = question("example.com", QueryType::A);
+    let ttls = |cache: &DnsCacheBase<FakeClock>| {
+        cache.lookup(&a).map(|cached| {
+            cached
+                .answers
+                .iter()
+                .map(RawRecord::ttl)
+                .collect::<Vec<_>>()
+        })
+    };
+    assert_eq!(ttls(&cache), Some(vec![100, 100]));
+
+    clock.0.store(40, Ordering::SeqCst);
+    assert_eq!(ttls(&cache), Some(vec![60, 60]));
+
+    // the whole RRset goes with its smallest TTL
+    clock.0.store(100, Ordering::SeqCst);
+    assert_eq!(ttls(&cache), None);
+}
+
+#[test]
+fn keeps_expired_entries_for_serving_stale() {
+    let clock = FakeClock::default();
+    let options = CacheOptions {
+        max_stale: Duration::from_secs(100),
+        ..CacheOptions::default()
+    };
+    let cache = DnsCacheBase::with_clock(clock.clone(), options);
+    cache.insert_rrsets(&records(vec![(
+        "example.com",
+        RecordData::A(Ipv4Addr::new(192, 0, 2, 1)),
+    )]));
+
+    let a = question("example.com", QueryType::A);
+    let stale_ttl = |cache: &DnsCacheBase<FakeClock>| {
+        cache
+            .lookup_stale(&a, 30)
+            .map(|cached| cached.answers[0].ttl())
+    };
+    assert_eq!(stale_ttl(&cache), Some(300));
+
+    clock.0.store(350, Ordering::SeqCst);
+    assert!(cache.lookup(&a).is_none());
+    assert_eq!(stale_ttl(&cache), Some(30));
+
+    clock.0.store(400, Ordering::SeqCst);
+    assert_eq!(stale_ttl(&cache), None);
+}
+
+#[test]
+fn flags_popular_entries_for_prefetching_once() {
+    let clock = FakeClock::default();
+    let options = CacheOptions {
+        prefetch_threshold_percent: 10,
+        prefetch_min_hits: 2,
+        ..CacheOptions::default()
+    };
+    let cache = DnsCacheBase::with_clock(clock.clone(), options);
+    cache.insert_rrsets(&records(vec![
+        (
+            "popular.example.com",
+            RecordData::A(Ipv4Addr::new(192, 0, 2, 1)),
+        ),
+        (
+            "rare.example.com",
+            RecordData::A(Ipv4Addr::new(192, 0, 2, 2)),
+        ),
+    ]));
+
+    let popular = question("popular.example.com", QueryType::A);
+    let rare = question("rare.example.com", QueryType::A);
+    let prefetch = |question: &Question| cache.lookup(question).map(|cached| cached.prefetch);
+
+    assert_eq!(prefetch(&popular), Some(false));
+
+    // within the last 10% of the TTL
+    clock.0.store(280, Ordering::SeqCst);
+    assert_eq!(prefetch(&popular), Some(true));
+    assert_eq!(prefetch(&popular), Some(false));
+    assert_eq!(prefetch(&rare), Some(false));
+
+    // a failed prefetch can be tried again
+    cache.release_prefetch(&popular);
+    assert_eq!(prefetch(&popular), Some(true));
+
+    let stats = cache.stats();
+    assert_eq!((stats.hits, stats.misses, stats.prefetches), (5, 0, 2));
+}
+
+#[test]
+fn evicts_least_recently_used_entries_beyond_the_size_limit() {
+    let options = CacheOptions {
+        max_size_bytes: 64 * 1024,
+        ..CacheOptions::default()
+    };
+    let cache = DnsCache::new(options);
+    cache.insert_rrsets(&records(vec![(
+        "hot.example.com",
+        RecordData::A(Ipv4Addr::new(192, 0, 2, 1)),
+    )]));
+
+    for i in 0..2000 {
+        let name = format!("host-{i}.example.com");
+        cache.insert_rrsets(&records(vec![(
+            name.as_str(),
+            RecordData::A(Ipv4Addr::new(192, 0, 2, 2)),
+        )]));
+        assert!(lookup(&cache, "hot.example.com", QueryType::A).is_some());
+    }
+
+    let stats = cache.stats();
+    assert!(stats.size_bytes <= 64 * 1024, "{stats}");
+    assert!(stats.entries < 2000, "{stats}");
+    assert!(lookup(&cache, "host-1999.example.com", QueryType::A).is_some());
+    assert!(lookup(&cache, "host-0.example.com", QueryType::A).is_none());
+}
+
+#[test]
+fn caches_within_budgets_too_small_to_split() {
+    let options = CacheOptions {
+        max_size_bytes: 100,
+        ..CacheOptions::default()
+    };
+    let cache = DnsCache::new(options);
+    cache.insert_rrsets(&records(vec![(
+        "www.example.com",
+        RecordData::A(Ipv4Addr::new(192, 0, 2, 1)),
+    )]));
+
+    assert!(lookup(&cache, "www.example.com", QueryType::A).is_some());
+}
+
+#[test]
+fn keeps_positive_and_negative_entries_within_one_size_limit() {
+    let options = CacheOptions {
+        max_size_bytes: 64 * 1024,
+        ..CacheOptions::default()
+    };
+    let cache = DnsCache::new(options);
+
+    for i in 0..2000 {
+        let name = format!("host-{i}.example.com");
+        cache.insert_rrsets(&records(vec![(
+            name.as_str(),
+            RecordData::A(Ipv4Addr::new(192, 0, 2, 2)),
+        )]));
+
+        let missing = question(&format!("missing-{i}.example.com"), QueryType::A);
+        cache.insert_response(
+            &missing,
+            &response(&missing, ResultCode::NameError, Vec::new(), Some(3600)),
+        );
+    }
+
+    let stats = cache.stats();
+    assert!(stats.size_bytes <= 64 * 1024, "{stats}");
+    assert!(lookup(&cache, "host-1999.example.com", QueryType::A).is_some());
+    let missing = question("missing-1999.example.com", QueryType::A);
+    assert!(cache.lookup(&missing).is_some());
+}
+
+#[test]
+fn restores_fresh_entries_from_a_snapshot() {
+    let path = std::env::temp_dir().join(format!("dns-test-{}-cache", std::process::id()));
+
+    let clock = FakeClock::default();
+    let cache = DnsCacheBase::with_clock(clock.clone(), CacheOptions::default());
+    let mut records = records(vec![
+        (
+            "long.example.com",
+            RecordData::A(Ipv4Addr::new(192, 0, 2, 1)),
+        ),
+        (
+            "short.example.com",
+            RecordData::A(Ipv4Addr::new(192, 0, 2, 2)),
+        ),
+    ]);
+    records[1] = records[1].clone().with_ttl(150);
+    cache.insert_rrsets(&records);
+    let missing = question("missing.example.com", QueryType::A);
+    cache.insert_response(
+        &missing,
+        &response(&missing, ResultCode::NameError, vec![], Some(3600)),
+    );
+
+    clock.0.store(100, Ordering::SeqCst);
+    assert_eq!(cache.save_snapshot(&path).unwrap(), 3);
+
+    let clock = FakeClock::default();
+    clock.0.store(200, Ordering::SeqCst);
+    let restored = DnsCacheBase::with_clock(clock.clone(), CacheOptions::default());
+    // the short-lived record expired in the meantime
+    assert_eq!(restored.load_snapshot(&path).unwrap(), 2);
+    std::fs::remove_file(&path).unwrap();
+
+    let long = question("long.example.com", QueryType::A);
+    assert_eq!(restored.lookup(&long).unwrap().answers[0].ttl(), 100);
+    let short = question("short.example.com", QueryType::A);
+    assert!(restored.lookup(&short).is_none());
+    let cached = restored.lookup(&missing).unwrap();
+    assert_eq!(cached.result_code, ResultCode::NameError);
+    assert_eq!(cached.authorities[0].ttl(), 100);
+}
+
+#[test]
+fn saves_snapshots_concurrently() {
+    let dir = std::env::temp_dir().join(format!("dns-test-{}-snapshots", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("cache");
+
+    let cache = Arc::new(DnsCache::new(CacheOptions::default()));
+    cache.insert_rrsets(&records(vec![(
+        "example.com",
+        RecordData::A(Ipv4Addr::new(192, 0, 2, 1)),
+    )]));
+
+    let saves = (0..8)
+        .map(|_| {
+            let cache = Arc::clone(&cache);
+            let path = path.clone();
+            std::thread::spawn(move || cache.save_snapshot(&path))
+        })
+        .collect::<Vec<_>>();
+    for save in saves {
+        assert_eq!(save.join().unwrap().unwrap(), 1);
+    }
+
+    let restored = DnsCache::new(CacheOptions::default());
+    assert_eq!(restored.load_snapshot(&path).unwrap(), 1);
+    // no temporary file left behind
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,58 @@
+use crate::config::Config;
+use std::net::SocketAddr;
+
+#[test]
+fn parses_config_file() {
+    let config = Config::from_toml(
+        r#"
+        [server]
+        listen = ["127.0.0.1:5353", "::1"]
+        workers = 3
+
+        [upstream]
+        servers = ["1.1.1.1"]
+
+        [[zones]]
+        file = "bind.txt"
+
+        [cache]
+        max_size_bytes = 65536
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        config.server.listen,
+        vec![
+            "127.0.0.1:5353".parse::<SocketAddr>().unwrap(),
+            "[::1]:53".parse::<SocketAddr>().unwrap()
+        ]
+    );
+    assert_eq!(config.workers(), 3);
+    assert_eq!(
+        config.upstream.servers,
+        vec!["1.1.1.1:53".parse::<SocketAddr>().unwrap()]
+    );
+    assert_eq!(config.cache.max_size_bytes, 65536);
+    assert!(!config.cache.serve_stale);
+
+    config.validate().unwrap();
+}
+
+#[test]
+fn rejects_invalid_config() {
+    assert!(Config::from_toml("[server]\nlisten = [\"not an address\"]").is_err());
+    assert!(Config::from_toml("[server]\nport = 53").is_err());
+
+    let mut config = Config::default();
+    config.upstream.servers.clear();
+    assert!(config.validate().is_err());
+
+    let mut config = Config::default();
+    config.server.workers = Some(0);
+    assert!(config.validate().is_err());
+
+    let config = Config::from_toml("[[zones]]\nfile = \"no-such-file.txt\"").unwrap();
+    let error = config.validate().unwrap_err().to_string();
+    assert!(error.contains("no-such-file.txt"), "{error}");
+}
@@ -0,0 +1,134 @@
+use crate::config::Config;
+use crate::models::{
+    new_packet_buffer, DnsPacket, DnsPacketBuilder, Edns, MessageType, QueryType, ResultCode,
+    DEFAULT_UDP_PAYLOAD_SIZE, MIN_UDP_PAYLOAD_SIZE,
+};
+use crate::tests::util::{request, start_server};
+use std::net::{SocketAddr, UdpSocket};
+use std::time::Duration;
+
+fn query(edns: Option<Edns>) -> DnsPacketBuilder {
+    let builder = request("www.example.com", QueryType::A).id(7);
+
+    match edns {
+        Some(edns) => builder.edns(edns),
+        None => builder,
+    }
+}
+
+#[test]
+fn keeps_opt_apart_from_additional_records() {
+    let mut buf = new_packet_buffer(MIN_UDP_PAYLOAD_SIZE);
+    query(Some(Edns::new(4096).with_dnssec_ok(true)))
+        .build()
+        .to_bytes(&mut buf)
+        .unwrap();
+
+    let packet = DnsPacket::from_bytes(&buf).unwrap();
+    assert!(packet.additional().is_empty());
+    let edns = packet.edns().unwrap();
+    assert_eq!(edns.udp_payload_size(), 4096);
+    assert_eq!(edns.version(), 0);
+    assert!(edns.dnssec_ok());
+
+    // too small sizes are read as 512
+    query(Some(Edns::new(100)))
+        .build()
+        .to_bytes(&mut buf)
+        .unwrap();
+    let packet = DnsPacket::from_bytes(&buf).unwrap();
+    assert_eq!(packet.edns().unwrap().udp_payload_size(), 512);
+}
+
+#[test]
+fn carries_extended_result_codes_in_opt() {
+    let response = |edns| {
+        query(edns)
+            .message_type(MessageType::Response)
+            .result_code(ResultCode::BadVersion)
+            .build()
+    };
+
+    let mut buf = new_packet_buffer(MIN_UDP_PAYLOAD_SIZE);
+    response(Some(Edns::new(DEFAULT_UDP_PAYLOAD_SIZE)))
+        .to_bytes(&mut buf)
+        .unwrap();
+    assert_eq!(buf[3] & 0x0F, 0);
+    assert_eq!(
+        DnsPacket::from_bytes(&buf).unwrap().result_code(),
+        ResultCode::BadVersion
+    );
+
+    // unknown codes are read as a server failure
+    buf[3] |= 7;
+    assert_eq!(
+        DnsPacket::from_bytes(&buf).unwrap().result_code(),
+        ResultCode::ServerFailure
+    );
+
+    assert!(response(None).to_bytes(&mut buf).is_err());
+}
+
+/// Sends a raw message to the server and parses its response.
+fn exchange(server: SocketAddr, message: &[u8]) -> DnsPacket {
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    client.send_to(message, server).unwrap();
+
+    let mut buf = new_packet_buffer(DEFAULT_UDP_PAYLOAD_SIZE);
+    client.recv_from(&mut buf).unwrap();
+    DnsPacket::from_bytes(&buf).unwrap()
+}
+
+#[test]
+fn answers_unsupported_versions_with_badvers() {
+    let addr = start_server(Config::default());
+
+    let mut buf = new_packet_buffer(MIN_UDP_PAYLOAD_SIZE);
+    let len = query(Some(Edns::new(DEFAULT_UDP_PAYLOAD_SIZE)))
+        .build()
+        .to_bytes(&mut buf)
+        .unwrap();
+    // the OPT record is last: the version is the second byte of its TTL
+    buf[len - 5] = 1;
+
+    let response = exchange(addr, &buf[..len]);
+    assert_eq!(response.result_code(), ResultCode::BadVersion);
+    assert!(response.answers().is_empty());
+    assert_eq!(response.edns().unwrap().version(), 0);
+}
+
+#[test]
+fn answers_malformed_opt_records_with_formerr() {
+    let addr = start_server(Config::default());
+
+    let mut buf = new_packet_buffer(MIN_UDP_PAYLOAD_SIZE);
+    let len = query(None).build().to_bytes(&mut buf).unwrap();
+    // root owner, type OPT, payload size 1232, no flags nor options
+    let opt = [0, 0, 41, 4, 208, 0, 0, 0, 0, 0, 0];
+    // owned by the question name instead
+    let misowned = [[0xC0, 12].as_slice(), &opt[1..]].concat();
+
+    // the answer count is at offset 6 of the header, the additional one at 10
+    let with_records = |count_offset: usize, records: &[&[u8]]| {
+        let mut message = buf[..len].to_vec();
+        message[count_offset + 1] = records.len() as u8;
+        message.extend(records.concat());
+        message
+    };
+
+    for message in [
+        with_records(10, &[&opt, &opt]),
+        with_records(6, &[&opt]),
+        with_records(10, &[&misowned]),
+    ] {
+        let response = exchange(addr, &message);
+        assert_eq!(response.id(), 7);
+        assert_eq!(response.result_code(), ResultCode::FormatError);
+    }
+
+    let response = exchange(addr, &with_records(10, &[&opt]));
+    assert_eq!(response.result_code(), ResultCode::NoError);
+}
@@ -0,0 +1,62 @@
+use crate::config::Config;
+use crate::server::ForwardRules;
+
+#[test]
+fn picks_the_longest_matching_suffix() {
+    let config = Config::from_toml(
+        r#"
+        [[forward]]
+        suffix = "corp.internal"
+        servers = ["10.0.0.53"]
+
+        [[forward]]
+        suffix = "eu.corp.internal."
+        servers = ["10.1.0.53", "10.1.0.54"]
+        timeout_ms = 500
+
+        [[forward]]
+        suffix = "consul"
+        servers = ["127.0.0.1:8600"]
+        "#,
+    )
+    .unwrap();
+    let rules = ForwardRules::new(&config).unwrap();
+
+    let suffix = |name: &str| {
+        rules
+            .find(&name.parse().unwrap())
+            .map(|(suffix, _)| suffix.to_string())
+    };
+    assert_eq!(suffix("corp.internal"), Some("corp.internal.".to_string()));
+    assert_eq!(
+        suffix("Host.CORP.internal"),
+        Some("corp.internal.".to_string())
+    );
+    assert_eq!(
+        suffix("host.eu.corp.internal"),
+        Some("eu.corp.internal.".to_string())
+    );
+    assert_eq!(suffix("web.service.consul"), Some("consul.".to_string()));
+    assert_eq!(suffix("notconsul"), None);
+    assert_eq!(suffix("example.com"), None);
+
+    let (_, upstreams) = rules.find(&"eu.corp.internal".parse().unwrap()).unwrap();
+    assert_eq!(upstreams.stats().len(), 2);
+}
+
+#[test]
+fn rejects_invalid_rules() {
+    let config = Config::from_toml(
+        "[[forward]]\nsuffix = \"consul\"\nservers = []\n[[zones]]\nfile = \"bind.txt\"",
+    )
+    .unwrap();
+    assert!(config.validate().is_err());
+
+    let config = Config::from_toml(
+        "[[forward]]\nsuffix = \"a\"\nservers = [\"1.1.1.1\"]\n\
+         [[forward]]\nsuffix = \"A.\"\nservers = [\"1.1.1.1\"]",
+    )
+    .unwrap();
+    let error = config.validate().unwrap_err().to_string();
+    assert!(error.contains("duplicate"), "{error}");
+}
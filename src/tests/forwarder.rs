@@ -0,0 +1,151 @@
+use crate::models::{
+    new_packet_buffer, DnsPacket, DnsPacketBuilder, MessageType, QueryType, RawRecordType,
+    RecordData, MIN_UDP_PAYLOAD_SIZE,
+};
+use crate::server::{Forwarder, ForwarderOptions};
+use crate::tests::util::question;
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn answer(request: &DnsPacket, id: u16, address: Ipv4Addr) -> Vec<u8> {
+    let question = request.questions()[0].clone();
+    let response = DnsPacketBuilder::default()
+        .id(id)
+        .message_type(MessageType::Response)
+        .with_question(question.clone())
+        .new_raw_record()
+        .name(question.name().clone())
+        .data(RecordData::A(address))
+        .add_raw_record(RawRecordType::Answer)
+        .unwrap()
+        .build();
+
+    let mut buf = new_packet_buffer(MIN_UDP_PAYLOAD_SIZE);
+    let len = response.to_bytes(&mut buf).unwrap();
+    buf.truncate(len);
+    buf
+}
+
+fn options(timeout: Duration, retries: usize) -> ForwarderOptions {
+    ForwarderOptions {
+        timeout,
+        retries,
+        ..ForwarderOptions::default()
+    }
+}
+
+#[test]
+fn drops_responses_not_matching_the_query() {
+    let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let upstream_addr = upstream.local_addr().unwrap();
+    let spoofer = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+    let server = thread::spawn(move || {
+        let mut buf = new_packet_buffer(MIN_UDP_PAYLOAD_SIZE);
+        let (_, client) = upstream.recv_from(&mut buf).unwrap();
+        let request = DnsPacket::from_bytes(&buf).unwrap();
+
+        // right ID from the wrong address
+        spoofer
+            .send_to(
+                &answer(&request, request.id(), Ipv4Addr::new(6, 6, 6, 6)),
+                client,
+            )
+            .unwrap();
+        // wrong ID from the right address
+        upstream
+            .send_to(
+                &answer(
+                    &request,
+                    request.id().wrapping_add(1),
+                    Ipv4Addr::new(6, 6, 6, 6),
+                ),
+                client,
+            )
+            .unwrap();
+        upstream
+            .send_to(
+                &answer(&request, request.id(), Ipv4Addr::new(1, 2, 3, 4)),
+                client,
+            )
+            .unwrap();
+    });
+
+    let forwarder = Forwarder::new(options(Duration::from_secs(2), 0));
+    let response = forwarder
+        .query(upstream_addr, &question("example.com", QueryType::A))
+        .unwrap();
+    server.join().unwrap();
+
+    assert_eq!(
+        response.answers()[0].data(),
+        &RecordData::A(Ipv4Addr::new(1, 2, 3, 4))
+    );
+}
+
+#[test]
+fn retries_and_times_out() {
+    let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let upstream_addr: SocketAddr = upstream.local_addr().unwrap();
+
+    let forwarder = Forwarder::new(options(Duration::from_millis(100), 2));
+
+    let start = Instant::now();
+    assert!(forwarder
+        .query(upstream_addr, &question("example.com", QueryType::A))
+        .is_err());
+    assert!(start.elapsed() >= Duration::from_millis(300));
+
+    upstream
+        .set_read_timeout(Some(Duration::from_millis(100)))
+        .unwrap();
+    let mut buf = new_packet_buffer(MIN_UDP_PAYLOAD_SIZE);
+    let mut ports = Vec::new();
+    while let Ok((_, src)) = upstream.recv_from(&mut buf) {
+        ports.push(src.port());
+    }
+    assert_eq!(ports.len(), 3);
+}
+
+#[test]
+fn retries_truncated_responses_over_tcp() {
+    let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let upstream_addr = upstream.local_addr().unwrap();
+    let listener = TcpListener::bind(upstream_addr).unwrap();
+
+    let server = thread::spawn(move || {
+        let mut buf = new_packet_buffer(MIN_UDP_PAYLOAD_SIZE);
+        let (_, client) = upstream.recv_from(&mut buf).unwrap();
+        let request = DnsPacket::from_bytes(&buf).unwrap();
+        let mut truncated = answer(&request, request.id(), Ipv4Addr::new(6, 6, 6, 6));
+        truncated[2] |= 0x02;
+        upstream.send_to(&truncated, client).unwrap();
+
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut len = [0u8; 2];
+        stream.read_exact(&mut len).unwrap();
+        let mut message = vec![0u8; u16::from_be_bytes(len) as usize];
+        stream.read_exact(&mut message).unwrap();
+        let request = DnsPacket::from_bytes(&message).unwrap();
+
+        let response = answer(&request, request.id(), Ipv4Addr::new(1, 2, 3, 4));
+        stream
+            .write_all(&(response.len() as u16).to_be_bytes())
+            .unwrap();
+        stream.write_all(&response).unwrap();
+    });
+
+    let forwarder = Forwarder::new(options(Duration::from_secs(2), 0));
+    let response = forwarder
+        .query(upstream_addr, &question("example.com", QueryType::A))
+        .unwrap();
+    server.join().unwrap();
+
+    assert!(!response.is_truncated());
+    assert_eq!(
+        response.answers()[0].data(),
+        &RecordData::A(Ipv4Addr::new(1, 2, 3, 4))
+    );
+}
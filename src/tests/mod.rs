@@ -1 +1,17 @@
+mod authoritative;
+mod cache;
+mod config;
+mod edns;
+mod forward_rules;
+mod forwarder;
+mod name;
+mod packet;
+mod prefetch;
+mod rdata;
+mod recursor;
+mod serve_stale;
 mod stress;
+mod tcp;
+mod upstreams;
+mod util;
+mod zone;
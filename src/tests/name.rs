@@ -0,0 +1,114 @@
+use crate::models::{
+    new_packet_buffer, DnsPacket, DnsPacketBuilder, Name, QueryType, RawRecordType, RecordData,
+    MIN_UDP_PAYLOAD_SIZE,
+};
+use std::collections::HashSet;
+
+fn name(text: &str) -> Name {
+    text.parse().unwrap()
+}
+
+#[test]
+fn parses_and_formats_presentation_format() {
+    let escaped = name("a\\.b.\\000\\255\\\\.Example.com.");
+    let labels = escaped.labels().collect::<Vec<_>>();
+    assert_eq!(
+        labels,
+        [&b"a.b"[..], b"\x00\xFF\\", b"Example", b"com"].to_vec()
+    );
+    assert_eq!(escaped.to_string(), "a\\.b.\\000\\255\\\\.Example.com.");
+    assert_eq!(name(&escaped.to_string()), escaped);
+
+    let origin = name("example.com");
+    assert_eq!(
+        Name::parse("www", &origin).unwrap(),
+        name("www.example.com")
+    );
+    assert_eq!(Name::parse("@", &origin).unwrap(), origin);
+    assert!(Name::parse(".", &origin).unwrap().is_root());
+    assert_eq!(Name::root().to_string(), ".");
+
+    assert!("a..b".parse::<Name>().is_err());
+    assert!("\\256".parse::<Name>().is_err());
+    assert!("a".repeat(64).parse::<Name>().is_err());
+    assert!(vec!["a".repeat(63); 4].join(".").parse::<Name>().is_err());
+}
+
+#[test]
+fn survives_the_wire_unchanged() {
+    let binary = name("\\000\\.bin.Example.COM");
+
+    let packet = DnsPacketBuilder::default()
+        .new_raw_record()
+        .name(Name::root())
+        .data(RecordData::NS(binary.clone()))
+        .add_raw_record(RawRecordType::Answer)
+        .unwrap()
+        .new_raw_record()
+        .name(binary.clone())
+        .data(RecordData::A("192.0.2.1".parse().unwrap()))
+        .add_raw_record(RawRecordType::Answer)
+        .unwrap()
+        .build();
+
+    let mut buf = new_packet_buffer(MIN_UDP_PAYLOAD_SIZE);
+    let len = packet.to_bytes(&mut buf).unwrap();
+    // the root is a single zero byte
+    assert_eq!(&buf[12..15], &[0, 0, u16::from(QueryType::NS) as u8]);
+
+    let parsed = DnsPacket::from_bytes(&buf[..len]).unwrap();
+    assert!(parsed.answers()[0].name().is_root());
+    assert_eq!(parsed.answers()[0].data(), &RecordData::NS(binary.clone()));
+    assert_eq!(
+        parsed.answers()[1].name().to_string(),
+        "\\000\\.bin.Example.COM."
+    );
+}
+
+#[test]
+fn compares_case_insensitively() {
+    assert_eq!(name("WWW.Example.COM"), name("www.example.com."));
+    assert_ne!(name("www.example.com"), name("www.example.org"));
+
+    let set = HashSet::from([name("Example.com")]);
+    assert!(set.contains(&name("EXAMPLE.COM")));
+}
+
+#[test]
+fn sorts_in_canonical_order() {
+    // the example of RFC 4034 6.1
+    let sorted = [
+        "example",
+        "a.example",
+        "yljkjljk.a.example",
+        "Z.a.example",
+        "zABC.a.EXAMPLE",
+        "z.example",
+        "\\001.z.example",
+        "*.z.example",
+        "\\200.z.example",
+    ]
+    .map(name);
+
+    let mut shuffled = sorted.clone();
+    shuffled.reverse();
+    shuffled.sort();
+    assert_eq!(shuffled, sorted);
+}
+
+#[test]
+fn walks_the_hierarchy() {
+    let www = name("www.example.com");
+
+    assert_eq!(www.parent(), Some(name("example.com")));
+    assert_eq!(Name::root().parent(), None);
+    assert_eq!(www.suffix(1), name("com"));
+    assert_eq!(www.suffix(5), www);
+    assert_eq!(www.ancestors().count(), 4);
+
+    assert!(www.is_subdomain_of(&name("EXAMPLE.com")));
+    assert!(www.is_subdomain_of(&www));
+    assert!(www.is_subdomain_of(&Name::root()));
+    assert!(!www.is_subdomain_of(&name("ww.example.com")));
+    assert!(!name("example.com").is_subdomain_of(&www));
+}
@@ -0,0 +1,139 @@
+use crate::models::{
+    new_packet_buffer, DnsPacket, DnsPacketBuilder, Edns, MessageType, QueryType, RawRecordType,
+    RecordData, MIN_UDP_PAYLOAD_SIZE,
+};
+use crate::tests::util::question;
+use std::net::Ipv4Addr;
+
+fn add_records(
+    mut builder: DnsPacketBuilder,
+    section: RawRecordType,
+    name: &str,
+    count: u8,
+) -> DnsPacketBuilder {
+    for i in 0..count {
+        builder = builder
+            .new_raw_record()
+            .name(name)
+            .data(RecordData::A(Ipv4Addr::new(10, 0, 0, i)))
+            .add_raw_record(section)
+            .unwrap();
+    }
+    builder
+}
+
+fn serialize(packet: DnsPacket) -> DnsPacket {
+    let mut buf = new_packet_buffer(MIN_UDP_PAYLOAD_SIZE);
+    let len = packet.to_bytes(&mut buf).unwrap();
+    DnsPacket::from_bytes(&buf[..len]).unwrap()
+}
+
+fn response() -> DnsPacketBuilder {
+    DnsPacketBuilder::default()
+        .message_type(MessageType::Response)
+        ._new_question()
+        .q_name("example.com")
+        .add_question()
+        .unwrap()
+}
+
+#[test]
+fn truncates_to_whole_rrsets() {
+    // the second RRset can't fit in 512 bytes
+    let builder = add_records(response(), RawRecordType::Answer, "a.example.com", 2);
+    let builder = add_records(builder, RawRecordType::Answer, "b.example.com", 40);
+    let builder = add_records(builder, RawRecordType::Additional, "c.example.com", 1);
+
+    let packet = serialize(builder.edns(Edns::new(4096)).build());
+    assert!(packet.is_truncated());
+    assert_eq!(packet.answers().len(), 2);
+    assert!(packet.additional().is_empty());
+    assert!(packet.edns().is_some());
+}
+
+#[test]
+fn drops_additional_records_without_truncating() {
+    let builder = add_records(response(), RawRecordType::Answer, "a.example.com", 2);
+    let builder = add_records(builder, RawRecordType::Additional, "b.example.com", 4);
+    let builder = add_records(builder, RawRecordType::Additional, "c.example.com", 40);
+
+    let packet = serialize(builder.build());
+    assert!(!packet.is_truncated());
+    assert_eq!(packet.answers().len(), 2);
+    assert_eq!(packet.additional().len(), 4);
+}
+
+#[test]
+fn truncates_referrals_missing_glue() {
+    let builder = DnsPacketBuilder::default()
+        .message_type(MessageType::Response)
+        .with_question(question("www.child.example.com", QueryType::A));
+    let builder = ["ns1.child.example.com", "ns2.child.example.com"]
+        .into_iter()
+        .fold(builder, |builder, host| {
+            builder
+                .new_raw_record()
+                .name("child.example.com")
+                .data(RecordData::NS(host.parse().unwrap()))
+                .add_raw_record(RawRecordType::Authority)
+                .unwrap()
+        });
+    let builder = add_records(
+        builder,
+        RawRecordType::Additional,
+        "ns1.child.example.com",
+        2,
+    );
+    let builder = add_records(
+        builder,
+        RawRecordType::Additional,
+        "ns2.child.example.com",
+        40,
+    );
+
+    let packet = serialize(builder.build());
+    assert!(packet.is_truncated());
+    assert_eq!(packet.authorities().len(), 2);
+    assert_eq!(packet.additional().len(), 2);
+}
+
+#[test]
+fn compresses_names_except_in_newer_rdata() {
+    let packet = response()
+        .new_raw_record()
+        .name("WWW.Example.COM")
+        .data(RecordData::CNAME("www.example.com".parse().unwrap()))
+        .add_raw_record(RawRecordType::Answer)
+        .unwrap()
+        .new_raw_record()
+        .name("www.example.com")
+        .data(RecordData::SRV {
+            priority: 0,
+            weight: 0,
+            port: 443,
+            target: "www.example.com".parse().unwrap(),
+        })
+        .add_raw_record(RawRecordType::Answer)
+        .unwrap()
+        .build();
+
+    let mut buf = new_packet_buffer(MIN_UDP_PAYLOAD_SIZE);
+    let len = packet.to_bytes(&mut buf).unwrap();
+
+    // header, question "example.com", owner "www" + pointer, CNAME RDATA as
+    // a pointer, owner as a pointer, SRV target in full
+    assert_eq!(len, 12 + (13 + 4) + (4 + 2 + 10 + 2) + (2 + 10 + 6 + 17));
+    assert!(buf[..len]
+        .windows(17)
+        .any(|window| window == b"\x03www\x07example\x03com\x00"));
+
+    let parsed = DnsPacket::from_bytes(&buf[..len]).unwrap();
+    assert_eq!(
+        parsed.answers()[0].data(),
+        &RecordData::CNAME("www.example.com".parse().unwrap())
+    );
+    // the case of each written label survives, pointers take that of the
+    // name they point to
+    assert_eq!(parsed.answers()[0].name().to_string(), "WWW.example.com.");
+    assert_eq!(parsed.answers()[1].name().to_string(), "WWW.example.com.");
+}
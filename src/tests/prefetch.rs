@@ -0,0 +1,61 @@
+use crate::config::Config;
+use crate::models::{DnsPacket, QueryType, ResultCode};
+use crate::tests::util::{query, request, spawn_upstream, start_server, wait_until, Reply};
+use std::net::UdpSocket;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Asks for `name` until the answer from upstream, the last one, gets
+/// refreshed before it expires.
+fn assert_prefetched(name: &str) {
+    // the first answer lives four seconds, the rest an hour
+    let queries = Arc::new(AtomicUsize::new(0));
+    let upstream = spawn_upstream({
+        let queries = Arc::clone(&queries);
+        move || match queries.fetch_add(1, Ordering::SeqCst) {
+            0 => Reply::Answer(ResultCode::NoError, 4),
+            _ => Reply::Answer(ResultCode::NoError, 3600),
+        }
+    });
+
+    let mut config = Config::default();
+    config.upstream.servers = vec![upstream];
+    config.cache.prefetch_threshold_percent = 50;
+    config.cache.prefetch_min_hits = 1;
+    let addr = start_server(config);
+
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let request = request(name, QueryType::A).build();
+    let ttl = |response: Vec<u8>| {
+        let response = DnsPacket::from_bytes(response).unwrap();
+        response.answers().last().unwrap().ttl()
+    };
+
+    assert_eq!(ttl(query(&client, addr, &request)), 4);
+
+    // answered from the cache, which refreshes the entry in the background
+    // once within the last half of the TTL
+    wait_until(|| {
+        let refreshed = queries.load(Ordering::SeqCst) == 2;
+        let ttl = ttl(query(&client, addr, &request));
+        // not a cache miss after the entry expired
+        assert!(refreshed || ttl <= 4);
+        ttl > 3000
+    });
+    assert_eq!(queries.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn refreshes_popular_entries_before_they_expire() {
+    assert_prefetched("www.prefetch.test");
+}
+
+#[test]
+fn refreshes_cname_targets_leaving_local_zones() {
+    // ftp.example.com is a CNAME to ftp.example.net in bind.txt
+    assert_prefetched("ftp.example.com");
+}
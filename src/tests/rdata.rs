@@ -0,0 +1,118 @@
+use crate::models::{
+    new_packet_buffer, parse_ttl, DnsPacket, DnsPacketBuilder, Name, QueryType, RawRecordType,
+    RecordData, MIN_UDP_PAYLOAD_SIZE,
+};
+
+fn origin() -> Name {
+    "example.com".parse().unwrap()
+}
+
+fn samples() -> Vec<(QueryType, &'static str, &'static str)> {
+    vec![
+        (QueryType::A, "192.0.2.1", "192.0.2.1"),
+        (QueryType::AAAA, "2001:db8::1", "2001:db8::1"),
+        (QueryType::NS, "ns1", "ns1.example.com."),
+        (QueryType::CNAME, "www.example.net.", "www.example.net."),
+        (QueryType::PTR, "host", "host.example.com."),
+        (QueryType::MX, "10 mail", "10 mail.example.com."),
+        (
+            QueryType::SOA,
+            "ns1 hostmaster 2024010101 1h 15m 1w 5m",
+            "ns1.example.com. hostmaster.example.com. 2024010101 3600 900 604800 300",
+        ),
+        (
+            QueryType::TXT,
+            "v=spf1 \"with\\\"quotes\\\"\" bin\\255",
+            "\"v=spf1\" \"with\\\"quotes\\\"\" \"bin\\255\"",
+        ),
+        (QueryType::SRV, "0 5 5060 sip", "0 5 5060 sip.example.com."),
+        (
+            QueryType::CAA,
+            "0 issue letsencrypt.org",
+            "0 issue \"letsencrypt.org\"",
+        ),
+        (QueryType::Unknown(65280), "\\# 3 0A0B0C", "\\# 3 0A0B0C"),
+    ]
+}
+
+/// Splits on spaces and drops the quotes the zone file tokenizer would strip.
+fn parse(query_type: QueryType, text: &str) -> RecordData {
+    let tokens = text
+        .split(' ')
+        .map(|token| {
+            token
+                .strip_prefix('"')
+                .and_then(|token| token.strip_suffix('"'))
+                .unwrap_or(token)
+        })
+        .collect::<Vec<_>>();
+    RecordData::from_text(query_type, &tokens, &origin()).unwrap()
+}
+
+#[test]
+fn parses_ttls() {
+    for (text, ttl) in [
+        ("3600", 3600),
+        ("1w2d3h4m5s", 788645),
+        ("2m30s", 150),
+        ("1H", 3600),
+        ("1h30", 3630),
+        ("99999999999", i32::MAX as u32),
+    ] {
+        assert_eq!(parse_ttl(text).unwrap(), ttl, "{text}");
+    }
+
+    for text in ["", "h", "hm", "1x", "1hh", "-1", "1 h"] {
+        assert!(parse_ttl(text).is_err(), "{text}");
+    }
+}
+
+#[test]
+fn text_round_trip() {
+    for (query_type, text, canonical) in samples() {
+        let data = parse(query_type, text);
+        assert_eq!(data.to_string(), canonical, "{query_type}");
+
+        let reparsed = parse(query_type, canonical);
+        assert_eq!(reparsed, data, "{query_type}");
+    }
+}
+
+#[test]
+fn wire_round_trip() {
+    let mut builder = DnsPacketBuilder::default();
+    for (query_type, text, _) in samples() {
+        builder = builder
+            .new_raw_record()
+            .name("example.com")
+            .query_type(query_type)
+            .data(parse(query_type, text))
+            .add_raw_record(RawRecordType::Answer)
+            .unwrap();
+    }
+    let packet = builder.build();
+
+    let mut buf = new_packet_buffer(MIN_UDP_PAYLOAD_SIZE);
+    packet.to_bytes(&mut buf).unwrap();
+    let parsed = DnsPacket::from_bytes(&buf).unwrap();
+
+    assert_eq!(parsed.answers().len(), samples().len());
+    for (parsed, original) in parsed.answers().iter().zip(packet.answers()) {
+        assert_eq!(parsed.to_string(), original.to_string());
+    }
+}
+
+#[test]
+fn generic_syntax_for_known_types() {
+    assert_eq!(
+        parse(QueryType::A, "\\# 4 C0000201"),
+        parse(QueryType::A, "192.0.2.1")
+    );
+
+    let tokens = ["\\#", "3", "C00002"];
+    assert!(RecordData::from_text(QueryType::A, &tokens, &origin()).is_err());
+    assert!(RecordData::from_text(QueryType::Unknown(65280), &["0A"], &origin()).is_err());
+    let tokens = ["\\#", "2", "€a"];
+    assert!(RecordData::from_text(QueryType::Unknown(99), &tokens, &origin()).is_err());
+    assert!(RecordData::from_text(QueryType::MX, &["10"], &origin()).is_err());
+}
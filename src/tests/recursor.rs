@@ -0,0 +1,320 @@
+use crate::cache::CacheOptions;
+use crate::models::{
+    new_packet_buffer, DnsPacket, DnsPacketBuilder, MessageType, QueryClass, QueryType, Question,
+    RawRecordType, RecordData, ResultCode, MIN_UDP_PAYLOAD_SIZE,
+};
+use crate::server::{Recursor, RecursorOptions};
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+type Handler = fn(&str, DnsPacketBuilder) -> DnsPacketBuilder;
+
+fn add(
+    builder: DnsPacketBuilder,
+    section: RawRecordType,
+    name: &str,
+    data: RecordData,
+) -> DnsPacketBuilder {
+    builder
+        .new_raw_record()
+        .name(name)
+        .ttl(300)
+        .data(data)
+        .add_raw_record(section)
+        .unwrap()
+}
+
+fn nxdomain(builder: DnsPacketBuilder) -> DnsPacketBuilder {
+    builder.result_code(ResultCode::NameError)
+}
+
+/// Serves queries with `handler`, counting them in `queries`.
+fn spawn_server(socket: UdpSocket, handler: Handler, queries: Arc<AtomicUsize>) {
+    thread::spawn(move || loop {
+        let mut buf = new_packet_buffer(MIN_UDP_PAYLOAD_SIZE);
+        let (_, client) = socket.recv_from(&mut buf).unwrap();
+        let request = DnsPacket::from_bytes(&buf).unwrap();
+        let question = request.questions()[0].clone();
+        queries.fetch_add(1, Ordering::SeqCst);
+
+        let builder = DnsPacketBuilder::default()
+            .id(request.id())
+            .message_type(MessageType::Response)
+            .with_question(question.clone());
+
+        let mut buf = new_packet_buffer(MIN_UDP_PAYLOAD_SIZE);
+        let name = question.name().to_string();
+        handler(name.trim_end_matches('.'), builder)
+            .build()
+            .to_bytes(&mut buf)
+            .unwrap();
+        socket.send_to(&buf, client).unwrap();
+    });
+}
+
+fn root(name: &str, builder: DnsPacketBuilder) -> DnsPacketBuilder {
+    if !name.ends_with("test") {
+        return nxdomain(builder);
+    }
+    let builder = add(
+        builder,
+        RawRecordType::Authority,
+        "test",
+        RecordData::NS("ns.test".parse().unwrap()),
+    );
+    add(
+        builder,
+        RawRecordType::Additional,
+        "ns.test",
+        RecordData::A(Ipv4Addr::new(127, 0, 0, 2)),
+    )
+}
+
+fn test_tld(name: &str, builder: DnsPacketBuilder) -> DnsPacketBuilder {
+    match name {
+        "ns.provider.test" => add(
+            builder,
+            RawRecordType::Answer,
+            name,
+            RecordData::A(Ipv4Addr::new(127, 0, 0, 3)),
+        ),
+        "www.cdn.test" => add(
+            builder,
+            RawRecordType::Answer,
+            name,
+            RecordData::A(Ipv4Addr::new(10, 0, 0, 1)),
+        ),
+        // glueless, the name server lives in another zone
+        _ if name.ends_with("example.test") => add(
+            builder,
+            RawRecordType::Authority,
+            "example.test",
+            RecordData::NS("ns.provider.test".parse().unwrap()),
+        ),
+        // glueless, but the name server can only be found through itself
+        _ if name.ends_with("loop.test") => add(
+            builder,
+            RawRecordType::Authority,
+            "loop.test",
+            RecordData::NS("ns.loop.test".parse().unwrap()),
+        ),
+        // the first server refers back to the zone itself
+        _ if name.ends_with("lame.test") => {
+            let builder =
+                ["ns1.lame.test", "ns2.lame.test"]
+                    .into_iter()
+                    .fold(builder, |builder, server| {
+                        add(
+                            builder,
+                            RawRecordType::Authority,
+                            "lame.test",
+                            RecordData::NS(server.parse().unwrap()),
+                        )
+                    });
+            let builder = add(
+                builder,
+                RawRecordType::Additional,
+                "ns1.lame.test",
+                RecordData::A(Ipv4Addr::new(127, 0, 0, 4)),
+            );
+            add(
+                builder,
+                RawRecordType::Additional,
+                "ns2.lame.test",
+                RecordData::A(Ipv4Addr::new(127, 0, 0, 5)),
+            )
+        }
+        // the only server refers up to the parent zone
+        _ if name.ends_with("upward.test") => {
+            let builder = add(
+                builder,
+                RawRecordType::Authority,
+                "upward.test",
+                RecordData::NS("ns.upward.test".parse().unwrap()),
+            );
+            add(
+                builder,
+                RawRecordType::Additional,
+                "ns.upward.test",
+                RecordData::A(Ipv4Addr::new(127, 0, 0, 4)),
+            )
+        }
+        _ => nxdomain(builder),
+    }
+}
+
+/// A server which doesn't know it serves `lame.test.` and `upward.test.`,
+/// referring back to where the query came from.
+fn lame(name: &str, builder: DnsPacketBuilder) -> DnsPacketBuilder {
+    let (zone, server) = if name.ends_with("lame.test") {
+        ("lame.test", "ns1.lame.test")
+    } else {
+        ("test", "ns.test")
+    };
+    add(
+        builder,
+        RawRecordType::Authority,
+        zone,
+        RecordData::NS(server.parse().unwrap()),
+    )
+}
+
+fn lame_test(name: &str, builder: DnsPacketBuilder) -> DnsPacketBuilder {
+    add(
+        builder,
+        RawRecordType::Answer,
+        name,
+        RecordData::A(Ipv4Addr::new(10, 0, 0, 2)),
+    )
+}
+
+fn example_test(name: &str, builder: DnsPacketBuilder) -> DnsPacketBuilder {
+    match name {
+        "www.example.test" => add(
+            builder,
+            RawRecordType::Answer,
+            name,
+            RecordData::CNAME("www.cdn.test".parse().unwrap()),
+        ),
+        "poisoned.example.test" => add(
+            add(
+                builder,
+                RawRecordType::Answer,
+                name,
+                RecordData::CNAME("www.cdn.test".parse().unwrap()),
+            ),
+            RawRecordType::Answer,
+            "www.cdn.test",
+            RecordData::A(Ipv4Addr::new(6, 6, 6, 6)),
+        ),
+        _ => nxdomain(builder),
+    }
+}
+
+/// Starts the root, `test.`, `example.test.` and the lame and working
+/// `lame.test.` servers on 127.0.0.1-5, sharing one port. Returns the recursor and the count of root queries.
+fn setup() -> (Recursor, Arc<AtomicUsize>) {
+    let root_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let port = root_socket.local_addr().unwrap().port();
+
+    let root_queries = Arc::new(AtomicUsize::new(0));
+    spawn_server(root_socket, root, Arc::clone(&root_queries));
+    for (ip, handler) in [
+        ([127, 0, 0, 2], test_tld as Handler),
+        ([127, 0, 0, 3], example_test),
+        ([127, 0, 0, 4], lame),
+        ([127, 0, 0, 5], lame_test),
+    ] {
+        let socket = UdpSocket::bind(SocketAddr::from((ip, port))).unwrap();
+        spawn_server(socket, handler, Arc::new(AtomicUsize::new(0)));
+    }
+
+    let recursor = Recursor::new(
+        RecursorOptions {
+            root_hints: vec![SocketAddr::from(([127, 0, 0, 1], port))],
+            query_port: port,
+            timeout: Duration::from_millis(500),
+            ..RecursorOptions::default()
+        },
+        4,
+        CacheOptions::default(),
+    );
+
+    (recursor, root_queries)
+}
+
+#[test]
+fn resolves_through_referrals_glueless_servers_and_cnames() {
+    let (recursor, root_queries) = setup();
+
+    let question = Question::new(
+        "www.example.test".parse().unwrap(),
+        QueryType::A,
+        QueryClass::IN,
+    );
+    let response = recursor.resolve(&question).unwrap();
+    assert_eq!(response.result_code(), ResultCode::NoError);
+    assert_eq!(
+        response
+            .answers()
+            .iter()
+            .map(|record| record.data().clone())
+            .collect::<Vec<_>>(),
+        [
+            RecordData::CNAME("www.cdn.test".parse().unwrap()),
+            RecordData::A(Ipv4Addr::new(10, 0, 0, 1)),
+        ]
+    );
+    assert_eq!(root_queries.load(Ordering::SeqCst), 1);
+
+    // the delegations are cached, the root isn't asked again
+    let question = Question::new(
+        "missing.example.test".parse().unwrap(),
+        QueryType::A,
+        QueryClass::IN,
+    );
+    let response = recursor.resolve(&question).unwrap();
+    assert_eq!(response.result_code(), ResultCode::NameError);
+    assert_eq!(root_queries.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn ignores_answers_outside_the_answering_zone() {
+    let (recursor, _) = setup();
+
+    let question = Question::new(
+        "poisoned.example.test".parse().unwrap(),
+        QueryType::A,
+        QueryClass::IN,
+    );
+    let response = recursor.resolve(&question).unwrap();
+    assert_eq!(
+        response
+            .answers()
+            .iter()
+            .map(|record| record.data().clone())
+            .collect::<Vec<_>>(),
+        [
+            RecordData::CNAME("www.cdn.test".parse().unwrap()),
+            RecordData::A(Ipv4Addr::new(10, 0, 0, 1)),
+        ]
+    );
+}
+
+#[test]
+fn detects_resolution_loops() {
+    let (recursor, _) = setup();
+
+    let question = Question::new(
+        "www.loop.test".parse().unwrap(),
+        QueryType::A,
+        QueryClass::IN,
+    );
+    assert!(recursor.resolve(&question).is_err());
+}
+
+#[test]
+fn skips_lame_servers() {
+    let (recursor, _) = setup();
+
+    let question = Question::new(
+        "www.lame.test".parse().unwrap(),
+        QueryType::A,
+        QueryClass::IN,
+    );
+    let response = recursor.resolve(&question).unwrap();
+    assert_eq!(
+        response.answers()[0].data(),
+        &RecordData::A(Ipv4Addr::new(10, 0, 0, 2))
+    );
+
+    let question = Question::new(
+        "www.upward.test".parse().unwrap(),
+        QueryType::A,
+        QueryClass::IN,
+    );
+    assert!(recursor.resolve(&question).is_err());
+}
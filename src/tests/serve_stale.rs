@@ -0,0 +1,83 @@
+use crate::config::Config;
+use crate::models::{DnsPacket, Edns, QueryType, RecordData, ResultCode, DEFAULT_UDP_PAYLOAD_SIZE};
+use crate::tests::util::{query, request, spawn_upstream, start_server, wait_until, Reply};
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Answers the first query with a record living one second, ignores the
+/// next `silent_queries` and answers the rest with a record living an hour,
+/// always taking 50ms. Counts the queries in `queries`.
+fn flaky_upstream(silent_queries: usize, queries: Arc<AtomicUsize>) -> SocketAddr {
+    spawn_upstream(move || {
+        thread::sleep(Duration::from_millis(50));
+        match queries.fetch_add(1, Ordering::SeqCst) {
+            0 => Reply::Answer(ResultCode::NoError, 1),
+            i if i <= silent_queries => Reply::Ignore,
+            _ => Reply::Answer(ResultCode::NoError, 3600),
+        }
+    })
+}
+
+fn ttl(response: &[u8]) -> u32 {
+    DnsPacket::from_bytes(response).unwrap().answers()[0].ttl()
+}
+
+/// Starts a server serving stale answers from `upstream`, gives it a query
+/// to cache and waits for the answer to expire. Returns the first stale
+/// response.
+fn expired_answer(upstream: SocketAddr) -> (SocketAddr, UdpSocket, DnsPacket, Vec<u8>) {
+    let mut config = Config::default();
+    config.upstream.servers = vec![upstream];
+    config.upstream.timeout_ms = 200;
+    config.upstream.retries = 0;
+    config.cache.serve_stale = true;
+    // stale data is served before any upstream can answer
+    config.cache.client_response_timeout_ms = 10;
+    let addr = start_server(config);
+
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+
+    let request = request("www.stale.test", QueryType::A)
+        .edns(Edns::new(DEFAULT_UDP_PAYLOAD_SIZE))
+        .build();
+    assert_eq!(ttl(&query(&client, addr, &request)), 1);
+
+    let mut stale = Vec::new();
+    wait_until(|| {
+        stale = query(&client, addr, &request);
+        ttl(&stale) == 30
+    });
+
+    (addr, client, request, stale)
+}
+
+#[test]
+fn answers_from_stale_data_when_upstreams_fail() {
+    let queries = Arc::new(AtomicUsize::new(0));
+    let (_, _, _, response) = expired_answer(flaky_upstream(usize::MAX, queries));
+
+    let stale = DnsPacket::from_bytes(&response).unwrap();
+    assert_eq!(
+        stale.answers()[0].data(),
+        &RecordData::A(Ipv4Addr::new(192, 0, 2, 1))
+    );
+    // Extended DNS Error option "Stale Answer"
+    assert!(response.windows(6).any(|w| w == [0, 15, 0, 2, 0, 3]));
+}
+
+#[test]
+fn keeps_refreshing_stale_data_until_upstreams_recover() {
+    let queries = Arc::new(AtomicUsize::new(0));
+    let (addr, client, request, _) = expired_answer(flaky_upstream(1, Arc::clone(&queries)));
+
+    // the refresh started by the stale answer fails, the retry asks again
+    // without any further client query and replaces the entry
+    wait_until(|| queries.load(Ordering::SeqCst) == 3);
+    wait_until(|| ttl(&query(&client, addr, &request)) > 3000);
+}
@@ -1,3 +1,4 @@
+use crate::config::Config;
 use crate::server::DnsServer;
 use rand::distributions::Alphanumeric;
 use rand::{random, Rng};
@@ -8,7 +9,7 @@ use std::thread;
 
 #[test]
 fn stress_test() {
-    thread::spawn(|| DnsServer::new().unwrap().run(12).unwrap());
+    thread::spawn(|| DnsServer::new(&Config::default()).unwrap().run(12).unwrap());
 
     let test_output_file_path: &'static str =
         concat!(env!("CARGO_MANIFEST_DIR"), "/target/stress_test_output.txt");
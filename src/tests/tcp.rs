@@ -0,0 +1,76 @@
+use crate::config::Config;
+use crate::models::{DnsPacket, QueryType, RecordData};
+use crate::tests::util::{request, start_server};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpStream};
+use std::time::Duration;
+
+/// Starts a server for the default zone file.
+fn start_tcp_server(max_connections_per_client: usize) -> SocketAddr {
+    let mut config = Config::default();
+    config.server.tcp_max_connections_per_client = max_connections_per_client;
+    start_server(config)
+}
+
+fn query(id: u16, name: &str) -> Vec<u8> {
+    let request = request(name, QueryType::A).id(id).build();
+
+    let mut buf = vec![0u8; 514];
+    let len = request.to_bytes(&mut buf[2..]).unwrap();
+    buf[..2].copy_from_slice(&(len as u16).to_be_bytes());
+    buf.truncate(len + 2);
+    buf
+}
+
+fn read_response(stream: &mut TcpStream) -> DnsPacket {
+    let mut len = [0u8; 2];
+    stream.read_exact(&mut len).unwrap();
+    let mut message = vec![0u8; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut message).unwrap();
+    DnsPacket::from_bytes(&message).unwrap()
+}
+
+#[test]
+fn answers_pipelined_queries() {
+    let addr = start_tcp_server(8);
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut queries = query(1, "www.example.com");
+    queries.extend(query(2, "mail.example.com"));
+    stream.write_all(&queries).unwrap();
+
+    let responses = (0..2)
+        .map(|_| {
+            let response = read_response(&mut stream);
+            (response.id(), response.answers()[0].data().clone())
+        })
+        .collect::<HashMap<_, _>>();
+
+    assert_eq!(
+        responses,
+        HashMap::from([
+            (1, RecordData::A(Ipv4Addr::new(192, 168, 254, 7))),
+            (2, RecordData::A(Ipv4Addr::new(192, 168, 254, 4))),
+        ])
+    );
+}
+
+#[test]
+fn limits_connections_per_client() {
+    let addr = start_tcp_server(1);
+
+    let mut first = TcpStream::connect(addr).unwrap();
+    first.write_all(&query(1, "www.example.com")).unwrap();
+    read_response(&mut first);
+
+    let mut second = TcpStream::connect(addr).unwrap();
+    second
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let _ = second.write_all(&query(2, "www.example.com"));
+    assert!(matches!(second.read(&mut [0u8; 2]), Ok(0) | Err(_)));
+}
@@ -0,0 +1,48 @@
+use crate::models::{QueryType, ResultCode};
+use crate::server::{ForwarderOptions, HealthOptions, UpstreamGroup};
+use crate::tests::util::{question, spawn_upstream, Reply};
+use std::time::Duration;
+
+#[test]
+fn fails_over_and_takes_failing_servers_out_of_rotation() {
+    let silent = spawn_upstream(|| Reply::Ignore);
+    let failing = spawn_upstream(|| Reply::Answer(ResultCode::ServerFailure, 0));
+    let healthy = spawn_upstream(|| Reply::Answer(ResultCode::NoError, 300));
+
+    let upstreams = UpstreamGroup::new(
+        &[silent, failing, healthy],
+        ForwarderOptions {
+            timeout: Duration::from_millis(100),
+            retries: 0,
+            ..ForwarderOptions::default()
+        },
+        HealthOptions {
+            max_failures: 1,
+            cooldown: Duration::from_secs(60),
+        },
+    );
+
+    let response = upstreams
+        .query(&question("example.com", QueryType::A))
+        .unwrap();
+    assert_eq!(response.result_code(), ResultCode::NoError);
+
+    let stats = upstreams.stats();
+    assert_eq!(
+        stats.iter().map(|s| s.healthy).collect::<Vec<_>>(),
+        [false, false, true]
+    );
+    assert_eq!(stats[0].failures, 1);
+    assert_eq!(stats[1].failures, 1);
+    assert!(stats[2].srtt.is_some());
+
+    // the healthy server now goes first, nothing is sent to the others
+    upstreams
+        .query(&question("example.com", QueryType::A))
+        .unwrap();
+    let stats = upstreams.stats();
+    assert_eq!(
+        stats.iter().map(|s| s.queries).collect::<Vec<_>>(),
+        [1, 1, 2]
+    );
+}
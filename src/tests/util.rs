@@ -0,0 +1,91 @@
+use crate::config::Config;
+use crate::models::{
+    new_packet_buffer, DnsPacket, DnsPacketBuilder, MessageType, QueryClass, QueryType, Question,
+    RawRecordType, RecordData, ResultCode, DEFAULT_UDP_PAYLOAD_SIZE, MIN_UDP_PAYLOAD_SIZE,
+};
+use crate::server::DnsServer;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub fn question(name: &str, query_type: QueryType) -> Question {
+    Question::new(name.parse().unwrap(), query_type, QueryClass::IN)
+}
+
+pub fn request(name: &str, query_type: QueryType) -> DnsPacketBuilder {
+    DnsPacketBuilder::default().with_question(question(name, query_type))
+}
+
+/// What an upstream from `spawn_upstream` does with a query.
+pub enum Reply {
+    /// Responds with the result code and, on success, a single A record for
+    /// 192.0.2.1 living the given TTL.
+    Answer(ResultCode, u32),
+    Ignore,
+}
+
+/// Handles every query as `handler` decides.
+pub fn spawn_upstream(mut handler: impl FnMut() -> Reply + Send + 'static) -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap();
+
+    thread::spawn(move || loop {
+        let mut buf = new_packet_buffer(MIN_UDP_PAYLOAD_SIZE);
+        let (_, client) = socket.recv_from(&mut buf).unwrap();
+        let Reply::Answer(result_code, ttl) = handler() else {
+            continue;
+        };
+        let request = DnsPacket::from_bytes(&buf).unwrap();
+        let question = request.questions()[0].clone();
+
+        let mut builder = DnsPacketBuilder::default()
+            .id(request.id())
+            .message_type(MessageType::Response)
+            .result_code(result_code)
+            .with_question(question.clone());
+        if result_code == ResultCode::NoError {
+            builder = builder
+                .new_raw_record()
+                .name(question.name().clone())
+                .ttl(ttl)
+                .data(RecordData::A(Ipv4Addr::new(192, 0, 2, 1)))
+                .add_raw_record(RawRecordType::Answer)
+                .unwrap();
+        }
+
+        let len = builder.build().to_bytes(&mut buf).unwrap();
+        socket.send_to(&buf[..len], client).unwrap();
+    });
+
+    addr
+}
+
+/// Starts a server for `config` on a free loopback port.
+pub fn start_server(mut config: Config) -> SocketAddr {
+    config.server.listen = vec!["127.0.0.1:0".parse().unwrap()];
+    let server = DnsServer::new(&config).unwrap();
+    let addr = server.local_addrs()[0];
+    thread::spawn(move || server.run(2).unwrap());
+
+    addr
+}
+
+/// Sends `request` over UDP and waits for the raw response.
+pub fn query(client: &UdpSocket, server: SocketAddr, request: &DnsPacket) -> Vec<u8> {
+    let mut buf = new_packet_buffer(DEFAULT_UDP_PAYLOAD_SIZE);
+    let len = request.to_bytes(&mut buf).unwrap();
+    client.send_to(&buf[..len], server).unwrap();
+
+    let (len, _) = client.recv_from(&mut buf).unwrap();
+    buf.truncate(len);
+    buf
+}
+
+/// Checks `condition` every 50ms until it holds, failing after five seconds.
+pub fn wait_until(mut condition: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !condition() {
+        assert!(Instant::now() < deadline, "condition not met in time");
+        thread::sleep(Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,431 @@
+use crate::config::ZoneConfig;
+use crate::models::{Name, QueryType, RecordData, ResultCode, MAX_CNAME_CHAIN_LENGTH};
+use crate::tests::util::question;
+use crate::zone::{ZoneRecord, ZoneStore};
+use std::net::Ipv4Addr;
+use std::path::PathBuf;
+
+fn write_zone_file(name: &str, content: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("dns-test-{}-{name}", std::process::id()));
+    std::fs::write(&path, content).unwrap();
+    path
+}
+
+fn name(text: &str) -> Name {
+    text.parse().unwrap()
+}
+
+fn load(origin: Option<&str>, file: PathBuf) -> anyhow::Result<ZoneStore> {
+    ZoneStore::load(&[ZoneConfig {
+        origin: origin.map(str::to_string),
+        file,
+    }])
+}
+
+/// The records of `query_type` the store answers with for `name`.
+fn lookup(store: &ZoneStore, name: &str, query_type: QueryType) -> Option<Vec<ZoneRecord>> {
+    store
+        .answer(&question(name, query_type))
+        .map(|answer| answer.answers)
+        .filter(|answers| !answers.is_empty())
+}
+
+#[test]
+fn looks_up_records_by_name() {
+    let path = write_zone_file(
+        "lookup.zone",
+        "example.com. 300 IN A 192.168.254.2\n\
+         ; comment\n\
+         www.example.com. 300 IN A 192.168.254.7 ; trailing comment\n\
+         www.example.com. 300 IN A 192.168.254.8\n",
+    );
+
+    let store = load(None, path).unwrap();
+    assert_eq!(store.records_count(), 3);
+
+    let records = lookup(&store, "WWW.Example.com", QueryType::A).unwrap();
+    assert_eq!(records.len(), 2);
+    assert!(records.iter().all(|r| r.query_type == QueryType::A));
+    assert_eq!(
+        records[1].data,
+        RecordData::A(Ipv4Addr::new(192, 168, 254, 8))
+    );
+
+    assert_eq!(
+        lookup(&store, "example.com", QueryType::A).unwrap().len(),
+        1
+    );
+    assert!(lookup(&store, "com", QueryType::A).is_none());
+    assert!(lookup(&store, "mail.example.com", QueryType::A).is_none());
+}
+
+#[test]
+fn parses_master_file_syntax() {
+    let included = write_zone_file("included.zone", "host 60 A 10.0.0.9\n");
+    let path = write_zone_file(
+        "master.zone",
+        &format!(
+            "$TTL 1h\n\
+             @ IN A 10.0.0.1\n\
+             \tA 10.0.0.2 ; owner is inherited\n\
+             www 2m30s IN A ( 10.0.0.3 ; multi-line\n\
+             \t)\n\
+             txt IN TXT \"quoted ; not a comment\"\n\
+             $ORIGIN sub\n\
+             deep IN 5 A 10.0.0.4\n\
+             absolute.example.net. A 10.0.0.5\n\
+             $INCLUDE {} other.org.\n\
+             after A 10.0.0.6\n",
+            included.display()
+        ),
+    );
+
+    let store = load(Some("example.com."), path).unwrap();
+    assert_eq!(store.records_count(), 8);
+
+    let apex = lookup(&store, "example.com", QueryType::A).unwrap();
+    assert_eq!(apex.len(), 2);
+    assert!(apex.iter().all(|r| r.ttl == 3600));
+
+    let www = &lookup(&store, "www.example.com", QueryType::A).unwrap()[0];
+    assert_eq!(www.ttl, 150);
+    assert_eq!(www.data, RecordData::A(Ipv4Addr::new(10, 0, 0, 3)));
+
+    assert_eq!(
+        lookup(&store, "txt.example.com", QueryType::TXT).unwrap()[0].data,
+        RecordData::TXT(vec![b"quoted ; not a comment".to_vec()])
+    );
+
+    assert_eq!(
+        lookup(&store, "deep.sub.example.com", QueryType::A).unwrap()[0].ttl,
+        5
+    );
+    assert!(lookup(&store, "absolute.example.net", QueryType::A).is_some());
+    assert_eq!(
+        lookup(&store, "host.other.org", QueryType::A).unwrap()[0].ttl,
+        60
+    );
+    assert!(lookup(&store, "after.sub.example.com", QueryType::A).is_some());
+}
+
+#[test]
+fn reports_syntax_errors_with_line_numbers() {
+    let path = write_zone_file(
+        "broken.zone",
+        "$TTL 300\nexample.com. IN A 192.168.254.2\n\nwww IN A not-an-address\n",
+    );
+    let error = format!("{:#}", load(None, path.clone()).err().unwrap());
+    assert!(error.contains(&format!("{}:4", path.display())), "{error}");
+
+    let path = write_zone_file("unbalanced.zone", "$TTL 300\n\n@ IN A ( 10.0.0.1\n");
+    let error = format!("{:#}", load(None, path).err().unwrap());
+    assert!(error.contains("line 3"), "{error}");
+
+    let path = write_zone_file("non-ascii.zone", "$TTL 300\nx TYPE99 \\# 2 €a\n");
+    let error = format!(
+        "{:#}",
+        load(Some("example.com"), path.clone()).err().unwrap()
+    );
+    assert!(error.contains(&format!("{}:2", path.display())), "{error}");
+
+    let path = write_zone_file("no-ttl.zone", "@ IN A 10.0.0.1\n");
+    assert!(load(Some("example.com"), path).is_err());
+}
+
+#[test]
+fn answers_authoritatively_inside_zones() {
+    let path = write_zone_file(
+        "authoritative.zone",
+        "$TTL 3600\n\
+         @ IN SOA ns1 hostmaster 1 3600 600 86400 300\n\
+         www IN A 192.0.2.1\n\
+         a.b IN A 192.0.2.2\n\
+         other.net. IN A 192.0.2.3\n",
+    );
+    let store = load(Some("example.com."), path).unwrap();
+
+    let answer = store
+        .answer(&question("WWW.example.com", QueryType::A))
+        .unwrap();
+    assert!(answer.authoritative);
+    assert_eq!(answer.result_code, ResultCode::NoError);
+    assert_eq!(answer.answers.len(), 1);
+    assert!(answer.authorities.is_empty());
+
+    // NODATA, for empty non-terminals too
+    for name in ["www.example.com", "b.example.com"] {
+        let answer = store.answer(&question(name, QueryType::AAAA)).unwrap();
+        assert!(answer.authoritative);
+        assert_eq!(answer.result_code, ResultCode::NoError);
+        assert!(answer.answers.is_empty());
+        assert_eq!(answer.authorities[0].query_type, QueryType::SOA);
+        // capped by the SOA minimum
+        assert_eq!(answer.authorities[0].ttl, 300);
+    }
+
+    let answer = store
+        .answer(&question("missing.example.com", QueryType::A))
+        .unwrap();
+    assert!(answer.authoritative);
+    assert_eq!(answer.result_code, ResultCode::NameError);
+    assert_eq!(answer.authorities[0].name, name("example.com"));
+
+    // outside of the zone only existing records are answered
+    let answer = store.answer(&question("other.net", QueryType::A)).unwrap();
+    assert!(!answer.authoritative);
+    assert!(store
+        .answer(&question("other.net", QueryType::AAAA))
+        .is_none());
+    assert!(store
+        .answer(&question("example.org", QueryType::A))
+        .is_none());
+}
+
+#[test]
+fn follows_cnames_through_local_data() {
+    let path = write_zone_file(
+        "cname.zone",
+        "$TTL 3600\n\
+         @ IN SOA ns1 hostmaster 1 3600 600 86400 300\n\
+         www IN CNAME web\n\
+         web IN CNAME host.other.org.\n\
+         out IN CNAME elsewhere.net.\n\
+         dangling IN CNAME missing\n\
+         loop1 IN CNAME loop2\n\
+         loop2 IN CNAME LOOP1\n\
+         $ORIGIN other.org.\n\
+         @ IN SOA ns1 hostmaster 1 3600 600 86400 300\n\
+         host IN A 192.0.2.1\n",
+    );
+    let store = load(Some("example.com."), path).unwrap();
+
+    let answer = store
+        .answer(&question("www.example.com", QueryType::A))
+        .unwrap();
+    assert!(answer.authoritative);
+    assert_eq!(answer.result_code, ResultCode::NoError);
+    let owners = answer.answers.iter().map(|r| r.name.to_string());
+    assert_eq!(
+        owners.collect::<Vec<_>>(),
+        ["www.example.com.", "web.example.com.", "host.other.org."]
+    );
+
+    // asking for the CNAME itself
+    let answer = store
+        .answer(&question("www.example.com", QueryType::CNAME))
+        .unwrap();
+    assert_eq!(answer.answers.len(), 1);
+
+    let answer = store
+        .answer(&question("dangling.example.com", QueryType::A))
+        .unwrap();
+    assert_eq!(answer.result_code, ResultCode::NameError);
+    assert_eq!(answer.answers.len(), 1);
+    assert_eq!(answer.authorities[0].query_type, QueryType::SOA);
+
+    let answer = store
+        .answer(&question("out.example.com", QueryType::A))
+        .unwrap();
+    assert_eq!(answer.chase, Some(name("elsewhere.net")));
+    assert_eq!(answer.answers.len(), 1);
+
+    let answer = store
+        .answer(&question("loop1.example.com", QueryType::A))
+        .unwrap();
+    assert_eq!(answer.result_code, ResultCode::ServerFailure);
+    assert_eq!(answer.answers.len(), 2);
+}
+
+#[test]
+fn synthesizes_records_from_wildcards() {
+    let path = write_zone_file(
+        "wildcard.zone",
+        "$TTL 3600\n\
+         @ IN SOA ns1 hostmaster 1 3600 600 86400 300\n\
+         *.preview IN A 192.0.2.1\n\
+         exists.preview IN TXT \"here\"\n\
+         host.ent.preview IN A 192.0.2.2\n\
+         *.alias IN CNAME exists.preview\n",
+    );
+    let store = load(Some("example.com."), path).unwrap();
+
+    for text in ["pr-1.preview.example.com", "a.b.preview.example.com"] {
+        let answer = store.answer(&question(text, QueryType::A)).unwrap();
+        assert_eq!(answer.result_code, ResultCode::NoError);
+        assert_eq!(answer.answers.len(), 1);
+        assert_eq!(answer.answers[0].name, name(text));
+        assert_eq!(
+            answer.answers[0].data,
+            RecordData::A(Ipv4Addr::new(192, 0, 2, 1))
+        );
+    }
+
+    // existing names and empty non-terminals are not matched
+    for text in ["exists.preview.example.com", "ent.preview.example.com"] {
+        let answer = store.answer(&question(text, QueryType::A)).unwrap();
+        assert_eq!(answer.result_code, ResultCode::NoError);
+        assert!(answer.answers.is_empty());
+    }
+
+    let answer = store
+        .answer(&question("pr-1.alias.example.com", QueryType::TXT))
+        .unwrap();
+    let owners = answer.answers.iter().map(|r| r.name.to_string());
+    assert_eq!(
+        owners.collect::<Vec<_>>(),
+        ["pr-1.alias.example.com.", "exists.preview.example.com."]
+    );
+
+    let answer = store
+        .answer(&question("other.example.com", QueryType::A))
+        .unwrap();
+    assert_eq!(answer.result_code, ResultCode::NameError);
+}
+
+#[test]
+fn refers_delegated_names_to_their_servers() {
+    let path = write_zone_file(
+        "delegation.zone",
+        "$TTL 3600\n\
+         @ IN SOA ns1 hostmaster 1 3600 600 86400 300\n\
+         @ IN NS ns1\n\
+         ns1 IN A 192.0.2.1\n\
+         team IN NS ns.team\n\
+         team IN NS ns.elsewhere.net.\n\
+         ns.team IN A 192.0.2.53\n\
+         ns.team IN AAAA 2001:db8::53\n\
+         occluded.team IN A 192.0.2.99\n\
+         alias IN CNAME www.team\n",
+    );
+    let store = load(Some("example.com."), path).unwrap();
+
+    for text in [
+        "team.example.com",
+        "www.team.example.com",
+        "occluded.team.example.com",
+    ] {
+        let answer = store.answer(&question(text, QueryType::A)).unwrap();
+        assert!(!answer.authoritative);
+        assert_eq!(answer.result_code, ResultCode::NoError);
+        assert!(answer.answers.is_empty());
+        assert_eq!(answer.authorities.len(), 2);
+        assert!(answer
+            .authorities
+            .iter()
+            .all(|record| record.query_type == QueryType::NS));
+        // no glue for the server outside of the delegated zone
+        assert_eq!(answer.additional.len(), 2);
+    }
+
+    // the NS records at the apex are no delegation
+    let answer = store
+        .answer(&question("ns1.example.com", QueryType::A))
+        .unwrap();
+    assert!(answer.authoritative);
+    assert_eq!(answer.answers.len(), 1);
+
+    let answer = store
+        .answer(&question("alias.example.com", QueryType::A))
+        .unwrap();
+    assert!(answer.authoritative);
+    assert_eq!(answer.answers.len(), 1);
+    assert_eq!(answer.chase, Some(name("www.team.example.com")));
+}
+
+#[test]
+fn adds_addresses_of_named_hosts() {
+    let path = write_zone_file(
+        "additional.zone",
+        "$TTL 3600\n\
+         @ IN SOA ns1 hostmaster 1 3600 600 86400 300\n\
+         @ IN NS ns1\n\
+         @ IN MX 10 mail\n\
+         @ IN MX 20 mail\n\
+         @ IN MX 30 mx.elsewhere.net.\n\
+         mail IN A 192.0.2.1\n\
+         mail IN AAAA 2001:db8::1\n\
+         mail IN A 192.0.2.2\n\
+         ns1 IN A 192.0.2.53\n\
+         _sip._tcp IN SRV 0 5 5060 sip\n\
+         sip IN AAAA 2001:db8::5060\n",
+    );
+    let store = load(Some("example.com."), path).unwrap();
+
+    let answer = store
+        .answer(&question("example.com", QueryType::MX))
+        .unwrap();
+    assert_eq!(answer.answers.len(), 3);
+    let types = answer.additional.iter().map(|record| record.query_type);
+    assert_eq!(
+        types.collect::<Vec<_>>(),
+        [QueryType::A, QueryType::A, QueryType::AAAA]
+    );
+
+    let answer = store
+        .answer(&question("example.com", QueryType::NS))
+        .unwrap();
+    assert_eq!(answer.additional.len(), 1);
+    assert_eq!(answer.additional[0].name, name("ns1.example.com"));
+
+    let answer = store
+        .answer(&question("_sip._tcp.example.com", QueryType::SRV))
+        .unwrap();
+    assert_eq!(answer.additional.len(), 1);
+    assert_eq!(answer.additional[0].query_type, QueryType::AAAA);
+
+    let answer = store
+        .answer(&question("mail.example.com", QueryType::A))
+        .unwrap();
+    assert!(answer.additional.is_empty());
+}
+
+#[test]
+fn leaves_out_addresses_below_zone_cuts() {
+    let path = write_zone_file(
+        "occluded-additional.zone",
+        "$TTL 3600\n\
+         @ IN SOA ns1 hostmaster 1 3600 600 86400 300\n\
+         @ IN NS ns1\n\
+         @ IN MX 10 mail.team\n\
+         ns1 IN A 192.0.2.1\n\
+         team IN NS ns.team\n\
+         ns.team IN A 192.0.2.53\n\
+         mail.team IN A 192.0.2.25\n",
+    );
+    let store = load(Some("example.com."), path).unwrap();
+
+    // the delegated zone is the authority for the mail host
+    let answer = store
+        .answer(&question("example.com", QueryType::MX))
+        .unwrap();
+    assert_eq!(answer.answers.len(), 1);
+    assert!(answer.additional.is_empty());
+
+    // glue is still taken from below the cut
+    let answer = store
+        .answer(&question("mail.team.example.com", QueryType::A))
+        .unwrap();
+    assert_eq!(answer.additional.len(), 1);
+    assert_eq!(answer.additional[0].name, name("ns.team.example.com"));
+}
+
+#[test]
+fn limits_cname_chains() {
+    let mut zone = String::from("$TTL 3600\n@ IN SOA ns1 hostmaster 1 3600 600 86400 300\n");
+    for i in 0..=MAX_CNAME_CHAIN_LENGTH {
+        zone.push_str(&format!("c{i} IN CNAME c{}\n", i + 1));
+    }
+    zone.push_str(&format!("c{} IN A 192.0.2.1\n", MAX_CNAME_CHAIN_LENGTH + 1));
+    let store = load(Some("example.com."), write_zone_file("chain.zone", &zone)).unwrap();
+
+    let answer = store
+        .answer(&question("c1.example.com", QueryType::A))
+        .unwrap();
+    assert_eq!(answer.result_code, ResultCode::NoError);
+    assert_eq!(answer.answers.len(), MAX_CNAME_CHAIN_LENGTH + 1);
+
+    let answer = store
+        .answer(&question("c0.example.com", QueryType::A))
+        .unwrap();
+    assert_eq!(answer.result_code, ResultCode::ServerFailure);
+}
@@ -0,0 +1,395 @@
+mod parser;
+
+use crate::config::ZoneConfig;
+use crate::models::{
+    Name, QueryClass, QueryType, Question, RecordData, ResultCode, MAX_CNAME_CHAIN_LENGTH,
+};
+use anyhow::Result;
+use rustc_hash::FxHashMap;
+
+#[derive(Clone, Debug)]
+pub struct ZoneRecord {
+    pub name: Name,
+    pub query_class: QueryClass,
+    pub query_type: QueryType,
+    pub ttl: u32,
+    pub data: RecordData,
+}
+
+/// An answer from local zone data.
+pub struct ZoneAnswer {
+    pub result_code: ResultCode,
+    /// Set for names inside a zone, below a SOA record.
+    pub authoritative: bool,
+    pub answers: Vec<ZoneRecord>,
+    /// The SOA record of the zone for negative answers, the NS records of
+    /// referrals.
+    pub authorities: Vec<ZoneRecord>,
+    /// Addresses of the hosts named by the answers, or glue of referrals.
+    pub additional: Vec<ZoneRecord>,
+    /// Where a CNAME chain leaves the zones, the rest of the answer is to be
+    /// looked up elsewhere.
+    pub chase: Option<Name>,
+}
+
+/// Node of the label tree: the root is the DNS root, every child is one label
+/// further from it, so `www.example.com` lives at `com -> example -> www`.
+/// Children are keyed by the lowercased label.
+#[derive(Default)]
+struct ZoneNode {
+    children: FxHashMap<Vec<u8>, ZoneNode>,
+    records: Vec<ZoneRecord>,
+}
+
+impl ZoneNode {
+    fn soa(&self) -> Option<&ZoneRecord> {
+        self.records
+            .iter()
+            .find(|record| record.query_type == QueryType::SOA)
+    }
+
+    fn has_type(&self, query_type: QueryType) -> bool {
+        self.records
+            .iter()
+            .any(|record| record.query_type == query_type)
+    }
+}
+
+/// Where a name leads in the label tree.
+struct Walk<'a> {
+    /// The node of the name or the wildcard matching it, `None` if neither
+    /// exists.
+    node: Option<&'a ZoneNode>,
+    /// Whether `node` is a wildcard to synthesize records from.
+    wildcard: bool,
+    /// SOA record of the zone the name is in.
+    soa: Option<&'a ZoneRecord>,
+    /// Set instead of `node` when the name is at or below a delegation of
+    /// the zone.
+    cut: Option<&'a ZoneNode>,
+}
+
+/// Zone data parsed once at startup and indexed by name, so a lookup walks
+/// at most one node per label of the queried name.
+#[derive(Default)]
+pub struct ZoneStore {
+    root: ZoneNode,
+    records_count: usize,
+}
+
+impl ZoneStore {
+    pub fn load(zones: &[ZoneConfig]) -> Result<Self> {
+        let mut store = Self::default();
+
+        for zone in zones {
+            let records = parser::parse_zone_file(&zone.file, zone.origin.as_deref())?;
+            log::info!(
+                "loaded {} records from {}",
+                records.len(),
+                zone.file.display()
+            );
+
+            for record in records {
+                store.insert(record);
+            }
+        }
+
+        Ok(store)
+    }
+
+    pub fn insert(&mut self, record: ZoneRecord) {
+        let mut node = &mut self.root;
+        for label in labels_from_root(&record.name) {
+            node = node.children.entry(label.to_ascii_lowercase()).or_default();
+        }
+
+        node.records.push(record);
+        self.records_count += 1;
+    }
+
+    /// Returns every record owned by `name`, or `None` if the name is unknown.
+    /// Answers `question` from the zones, following CNAMEs through local
+    /// data and synthesizing records from wildcards. Names at or below a
+    /// zone cut get a referral to the servers of the delegated zone. Names
+    /// inside a zone are answered authoritatively, with NXDOMAIN or NODATA
+    /// if need be, so they are never looked up elsewhere. Names outside are
+    /// only answered when they own records of the asked type or a CNAME.
+    pub fn answer(&self, question: &Question) -> Option<ZoneAnswer> {
+        let mut name = question.name().clone();
+        let mut answers = Vec::<ZoneRecord>::new();
+        let mut authoritative = None;
+
+        loop {
+            let walk = self.walk(&name);
+            if let Some(cut) = walk.cut {
+                if answers.is_empty() {
+                    return Some(self.referral(cut, question.query_class()));
+                }
+                // the rest of the chain is up to the delegated zone
+                return Some(ZoneAnswer {
+                    result_code: ResultCode::NoError,
+                    authoritative: authoritative.unwrap_or_default(),
+                    answers,
+                    authorities: Vec::new(),
+                    additional: Vec::new(),
+                    chase: Some(name),
+                });
+            }
+
+            let soa = walk
+                .soa
+                .filter(|soa| soa.query_class == question.query_class());
+            // the AA bit is about the question name, not the CNAME targets
+            let authoritative = *authoritative.get_or_insert(soa.is_some());
+            let records = walk
+                .node
+                .map(|node| node.records.as_slice())
+                .unwrap_or_default()
+                .iter()
+                .filter(|record| record.query_class == question.query_class());
+            // synthesized records are owned by the name asked for
+            let owned = |record: &ZoneRecord| ZoneRecord {
+                name: if walk.wildcard {
+                    name.clone()
+                } else {
+                    record.name.clone()
+                },
+                ..record.clone()
+            };
+
+            let before = answers.len();
+            answers.extend(
+                records
+                    .clone()
+                    .filter(|record| record.query_type == question.query_type())
+                    .map(owned),
+            );
+            if answers.len() > before {
+                let additional = self.host_addresses(&answers, question.query_class());
+                return Some(ZoneAnswer {
+                    result_code: ResultCode::NoError,
+                    authoritative,
+                    answers,
+                    authorities: Vec::new(),
+                    additional,
+                    chase: None,
+                });
+            }
+
+            let cname = records.clone().find_map(|record| match &record.data {
+                RecordData::CNAME(target) if question.query_type() != QueryType::CNAME => {
+                    Some((record, target))
+                }
+                _ => None,
+            });
+            if let Some((record, target)) = cname {
+                answers.push(owned(record));
+
+                if answers.len() > MAX_CNAME_CHAIN_LENGTH
+                    || answers.iter().any(|record| record.name == *target)
+                {
+                    log::warn!("CNAME loop or overlong chain at {}", question.name());
+                    return Some(ZoneAnswer {
+                        result_code: ResultCode::ServerFailure,
+                        authoritative,
+                        answers,
+                        authorities: Vec::new(),
+                        additional: Vec::new(),
+                        chase: None,
+                    });
+                }
+
+                name = target.clone();
+                continue;
+            }
+
+            return match soa {
+                Some(soa) => Some(ZoneAnswer {
+                    result_code: if walk.node.is_some() {
+                        ResultCode::NoError
+                    } else {
+                        ResultCode::NameError
+                    },
+                    authoritative,
+                    answers,
+                    authorities: vec![negative_soa(soa)],
+                    additional: Vec::new(),
+                    chase: None,
+                }),
+                None if answers.is_empty() => None,
+                None => Some(ZoneAnswer {
+                    result_code: ResultCode::NoError,
+                    authoritative,
+                    answers,
+                    authorities: Vec::new(),
+                    additional: Vec::new(),
+                    chase: Some(name),
+                }),
+            };
+        }
+    }
+
+    pub fn records_count(&self) -> usize {
+        self.records_count
+    }
+
+    /// A referral to the servers of a delegated zone, along with the
+    /// addresses of those named inside it as glue.
+    fn referral(&self, cut: &ZoneNode, query_class: QueryClass) -> ZoneAnswer {
+        let servers = cut
+            .records
+            .iter()
+            .filter(|record| {
+                record.query_type == QueryType::NS && record.query_class == query_class
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let in_bailiwick = servers
+            .iter()
+            .filter(|server| {
+                matches!(&server.data, RecordData::NS(host) if host.is_subdomain_of(&server.name))
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        let glue = self.glue(&in_bailiwick, query_class);
+
+        ZoneAnswer {
+            result_code: ResultCode::NoError,
+            authoritative: false,
+            answers: Vec::new(),
+            authorities: servers,
+            additional: glue,
+            chase: None,
+        }
+    }
+
+    /// The A and AAAA records of the hosts named by MX, NS and SRV records,
+    /// grouped in RRsets so the packet can drop them whole. Hosts at or below
+    /// a zone cut are left out, their records are occluded.
+    fn host_addresses(&self, records: &[ZoneRecord], query_class: QueryClass) -> Vec<ZoneRecord> {
+        let nodes = hosts(records).into_iter().filter_map(|host| {
+            let walk = self.walk(host);
+            if walk.cut.is_some() || walk.wildcard {
+                return None;
+            }
+            walk.node
+        });
+
+        addresses(nodes, query_class)
+    }
+
+    /// The addresses of in-bailiwick name servers, which live below the cut
+    /// they serve.
+    fn glue(&self, servers: &[ZoneRecord], query_class: QueryClass) -> Vec<ZoneRecord> {
+        let nodes = hosts(servers)
+            .into_iter()
+            .filter_map(|host| self.find(host));
+
+        addresses(nodes, query_class)
+    }
+
+    /// The node of `name`, ignoring zone cuts and wildcards.
+    fn find(&self, name: &Name) -> Option<&ZoneNode> {
+        labels_from_root(name).try_fold(&self.root, |node, label| {
+            node.children.get(&label.to_ascii_lowercase())
+        })
+    }
+
+    fn walk(&self, name: &Name) -> Walk<'_> {
+        let mut node = &self.root;
+        let mut soa = node.soa();
+
+        for label in labels_from_root(name) {
+            match node.children.get(&label.to_ascii_lowercase()) {
+                Some(child) => {
+                    node = child;
+                    if node.soa().is_some() {
+                        soa = node.soa();
+                    } else if soa.is_some() && node.has_type(QueryType::NS) {
+                        // NS records below the apex delegate the rest of the
+                        // tree, whatever lies below is occluded
+                        return Walk {
+                            node: None,
+                            wildcard: false,
+                            soa,
+                            cut: Some(node),
+                        };
+                    }
+                }
+                None => {
+                    // the deepest existing node is the closest encloser, its
+                    // wildcard child stands for the missing names below it
+                    // (RFC 4592 3.3.1)
+                    let wildcard = node.children.get(b"*".as_slice());
+                    return Walk {
+                        node: wildcard,
+                        wildcard: wildcard.is_some(),
+                        soa,
+                        cut: None,
+                    };
+                }
+            }
+        }
+
+        Walk {
+            node: Some(node),
+            wildcard: false,
+            soa,
+            cut: None,
+        }
+    }
+}
+
+/// The hosts named by MX, NS and SRV records, without duplicates.
+fn hosts(records: &[ZoneRecord]) -> Vec<&Name> {
+    let mut hosts = Vec::new();
+    for record in records {
+        let host = match &record.data {
+            RecordData::MX { exchange, .. } => exchange,
+            RecordData::NS(host) => host,
+            RecordData::SRV { target, .. } => target,
+            _ => continue,
+        };
+        if !hosts.contains(&host) {
+            hosts.push(host);
+        }
+    }
+    hosts
+}
+
+/// The A then AAAA records of every node.
+fn addresses<'a>(
+    nodes: impl Iterator<Item = &'a ZoneNode>,
+    query_class: QueryClass,
+) -> Vec<ZoneRecord> {
+    let mut addresses = Vec::new();
+    for node in nodes {
+        for query_type in [QueryType::A, QueryType::AAAA] {
+            addresses.extend(
+                node.records
+                    .iter()
+                    .filter(|record| {
+                        record.query_type == query_type && record.query_class == query_class
+                    })
+                    .cloned(),
+            );
+        }
+    }
+    addresses
+}
+
+/// The SOA record for the authority section of a negative answer, its TTL
+/// capped by the SOA minimum as RFC 2308 3 asks.
+fn negative_soa(soa: &ZoneRecord) -> ZoneRecord {
+    let ttl = match soa.data {
+        RecordData::SOA { minimum, .. } => soa.ttl.min(minimum),
+        _ => soa.ttl,
+    };
+
+    ZoneRecord { ttl, ..soa.clone() }
+}
+
+fn labels_from_root(name: &Name) -> impl Iterator<Item = &[u8]> {
+    name.labels().collect::<Vec<_>>().into_iter().rev()
+}
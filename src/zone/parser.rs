@@ -0,0 +1,306 @@
+use crate::models::{parse_ttl, Name, QueryClass, QueryType, RecordData};
+use crate::zone::ZoneRecord;
+use anyhow::{anyhow, bail, Context, Result};
+use std::path::{Path, PathBuf};
+
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+/// Parses an RFC 1035 master file. `origin` is the initial `$ORIGIN`, the
+/// root is used when it's not given.
+pub(in crate::zone) fn parse_zone_file(
+    path: &Path,
+    origin: Option<&str>,
+) -> Result<Vec<ZoneRecord>> {
+    let origin = match origin {
+        Some(origin) => origin.parse()?,
+        None => Name::root(),
+    };
+
+    let mut records = Vec::new();
+    ZoneFileParser::new(origin, 0).parse_file(path, &mut records)?;
+
+    Ok(records)
+}
+
+#[derive(Debug)]
+struct Token {
+    text: String,
+    quoted: bool,
+}
+
+/// One logical line of a master file: parentheses join physical lines.
+#[derive(Debug)]
+struct Entry {
+    line: usize,
+    owner_omitted: bool,
+    tokens: Vec<Token>,
+}
+
+fn tokenize(text: &str) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+    let mut depth = 0usize;
+
+    let mut entry = Entry {
+        line,
+        owner_omitted: false,
+        tokens: Vec::new(),
+    };
+    let mut at_line_start = true;
+
+    while let Some(c) = chars.next() {
+        if at_line_start && depth == 0 {
+            entry.line = line;
+            entry.owner_omitted = c == ' ' || c == '\t';
+        }
+        at_line_start = false;
+
+        match c {
+            '\n' => {
+                if depth == 0 {
+                    if !entry.tokens.is_empty() {
+                        entries.push(entry);
+                    }
+                    entry = Entry {
+                        line: line + 1,
+                        owner_omitted: false,
+                        tokens: Vec::new(),
+                    };
+                    at_line_start = true;
+                }
+                line += 1;
+            }
+            ' ' | '\t' | '\r' => {}
+            ';' => while chars.next_if(|&c| c != '\n').is_some() {},
+            '(' => depth += 1,
+            ')' => {
+                if depth == 0 {
+                    bail!("line {line}: unbalanced ')'");
+                }
+                depth -= 1;
+            }
+            '"' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => {
+                            text.push('\\');
+                            text.push(
+                                chars
+                                    .next()
+                                    .ok_or_else(|| anyhow!("line {line}: unterminated string"))?,
+                            );
+                        }
+                        Some('\n') | None => bail!("line {line}: unterminated string"),
+                        Some(c) => text.push(c),
+                    }
+                }
+                entry.tokens.push(Token { text, quoted: true });
+            }
+            c => {
+                let mut text = String::from(c);
+                if c == '\\' {
+                    if let Some(escaped) = chars.next() {
+                        text.push(escaped);
+                    }
+                }
+                while let Some(c) = chars
+                    .next_if(|&c| !matches!(c, ' ' | '\t' | '\r' | '\n' | ';' | '(' | ')' | '"'))
+                {
+                    text.push(c);
+                    if c == '\\' {
+                        if let Some(escaped) = chars.next() {
+                            text.push(escaped);
+                        }
+                    }
+                }
+                entry.tokens.push(Token {
+                    text,
+                    quoted: false,
+                });
+            }
+        }
+    }
+
+    if depth > 0 {
+        bail!("line {}: unbalanced '('", entry.line);
+    }
+    if !entry.tokens.is_empty() {
+        entries.push(entry);
+    }
+
+    Ok(entries)
+}
+
+struct ZoneFileParser {
+    origin: Name,
+    default_ttl: Option<u32>,
+    last_owner: Option<Name>,
+    last_ttl: Option<u32>,
+    last_class: QueryClass,
+    include_depth: usize,
+}
+
+impl ZoneFileParser {
+    fn new(origin: Name, include_depth: usize) -> Self {
+        Self {
+            origin,
+            default_ttl: None,
+            last_owner: None,
+            last_ttl: None,
+            last_class: QueryClass::IN,
+            include_depth,
+        }
+    }
+
+    fn parse_file(&mut self, path: &Path, records: &mut Vec<ZoneRecord>) -> Result<()> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed reading {}", path.display()))?;
+
+        let entries =
+            tokenize(&text).with_context(|| format!("{}: broken zone file", path.display()))?;
+
+        for entry in entries {
+            self.parse_entry(path, &entry, records)
+                .with_context(|| format!("{}:{}: broken zone file", path.display(), entry.line))?;
+        }
+
+        Ok(())
+    }
+
+    fn parse_entry(
+        &mut self,
+        path: &Path,
+        entry: &Entry,
+        records: &mut Vec<ZoneRecord>,
+    ) -> Result<()> {
+        let first = &entry.tokens[0];
+
+        if !entry.owner_omitted && !first.quoted && first.text.starts_with('$') {
+            return self.parse_directive(path, entry, records);
+        }
+
+        records.push(self.parse_record(entry)?);
+
+        Ok(())
+    }
+
+    fn parse_directive(
+        &mut self,
+        path: &Path,
+        entry: &Entry,
+        records: &mut Vec<ZoneRecord>,
+    ) -> Result<()> {
+        let args = &entry.tokens[1..];
+
+        match entry.tokens[0].text.to_ascii_uppercase().as_str() {
+            "$ORIGIN" => {
+                let [origin] = args else {
+                    bail!("$ORIGIN expects exactly one argument");
+                };
+                self.origin = Name::parse(&origin.text, &self.origin)?;
+            }
+            "$TTL" => {
+                let [ttl] = args else {
+                    bail!("$TTL expects exactly one argument");
+                };
+                self.default_ttl = Some(parse_ttl(&ttl.text)?);
+            }
+            "$INCLUDE" => {
+                let (file, origin) = match args {
+                    [file] => (file, self.origin.clone()),
+                    [file, origin] => (file, Name::parse(&origin.text, &self.origin)?),
+                    _ => bail!("$INCLUDE expects a file name and an optional origin"),
+                };
+
+                if self.include_depth >= MAX_INCLUDE_DEPTH {
+                    bail!("$INCLUDE nested too deeply");
+                }
+
+                let mut include_path = PathBuf::from(&file.text);
+                if include_path.is_relative() {
+                    if let Some(dir) = path.parent() {
+                        include_path = dir.join(include_path);
+                    }
+                }
+
+                let mut parser = ZoneFileParser::new(origin, self.include_depth + 1);
+                parser.default_ttl = self.default_ttl;
+                parser.last_ttl = self.last_ttl;
+                parser.last_class = self.last_class;
+                parser.parse_file(&include_path, records)?;
+            }
+            directive => bail!("unsupported directive {directive}"),
+        }
+
+        Ok(())
+    }
+
+    fn parse_record(&mut self, entry: &Entry) -> Result<ZoneRecord> {
+        let mut tokens = entry.tokens.iter().peekable();
+
+        let name = if entry.owner_omitted {
+            self.last_owner
+                .clone()
+                .ok_or_else(|| anyhow!("no owner name and no previous record"))?
+        } else {
+            let owner = tokens.next().unwrap();
+            Name::parse(&owner.text, &self.origin)?
+        };
+
+        let mut ttl = None;
+        let mut query_class = None;
+        for _ in 0..2 {
+            let Some(token) = tokens.peek() else {
+                break;
+            };
+
+            if ttl.is_none() && token.text.starts_with(|c: char| c.is_ascii_digit()) {
+                ttl = Some(parse_ttl(&token.text)?);
+            } else if query_class.is_none() {
+                match QueryClass::try_from(token.text.as_str()) {
+                    Ok(class) => query_class = Some(class),
+                    Err(_) => break,
+                }
+            } else {
+                break;
+            }
+            tokens.next();
+        }
+
+        let type_token = tokens
+            .next()
+            .ok_or_else(|| anyhow!("missing record type"))?;
+        let rdata = tokens.collect::<Vec<_>>();
+
+        let ttl = ttl
+            .or(self.default_ttl)
+            .or(self.last_ttl)
+            .ok_or_else(|| anyhow!("no TTL specified and no $TTL directive"))?;
+        let query_class = query_class.unwrap_or(self.last_class);
+
+        self.last_owner = Some(name.clone());
+        self.last_ttl = Some(ttl);
+        self.last_class = query_class;
+
+        let query_type = QueryType::try_from(type_token.text.as_str())
+            .with_context(|| format!("unknown record type {}", type_token.text))?;
+        let rdata = rdata
+            .iter()
+            .map(|token| token.text.as_str())
+            .collect::<Vec<_>>();
+        let data = RecordData::from_text(query_type, &rdata, &self.origin)
+            .with_context(|| format!("invalid {query_type} record"))?;
+
+        Ok(ZoneRecord {
+            name,
+            query_class,
+            query_type,
+            ttl,
+            data,
+        })
+    }
+}